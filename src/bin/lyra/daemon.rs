@@ -1,6 +1,7 @@
 use clap;
 use gasket::daemon::Daemon;
 use lyra::framework::*;
+use lyra::recorder;
 use lyra::reducers;
 use lyra::sources;
 use lyra::storage;
@@ -13,6 +14,7 @@ use crate::console;
 #[derive(Deserialize)]
 struct ConfigRoot {
     source: sources::Config,
+    recorder: Option<recorder::Config>,
     reducer: reducers::Config,
     storage: storage::Config,
     intersect: IntersectConfig,
@@ -67,15 +69,42 @@ fn define_gasket_policy(config: Option<&gasket::retries::Policy>) -> gasket::run
 
 fn connect_stages(
     mut source: sources::Bootstrapper,
+    recorder: Option<recorder::Stage>,
     mut reducer: reducers::Bootstrapper,
     mut storage: storage::Bootstrapper,
     policy: gasket::runtime::Policy,
 ) -> Result<Daemon, Error> {
-    gasket::messaging::tokio::connect_ports(source.borrow_output(), reducer.borrow_input(), 100);
+    let recorder = match recorder {
+        Some(mut recorder) => {
+            gasket::messaging::tokio::connect_ports(
+                source.borrow_output(),
+                &mut recorder.input,
+                100,
+            );
+            gasket::messaging::tokio::connect_ports(
+                &mut recorder.output,
+                reducer.borrow_input(),
+                100,
+            );
+            Some(recorder)
+        }
+        None => {
+            gasket::messaging::tokio::connect_ports(
+                source.borrow_output(),
+                reducer.borrow_input(),
+                100,
+            );
+            None
+        }
+    };
+
     gasket::messaging::tokio::connect_ports(reducer.borrow_output(), storage.borrow_input(), 100);
 
     let mut tethers = vec![];
     tethers.push(source.spawn(policy.clone()));
+    if let Some(recorder) = recorder {
+        tethers.push(gasket::runtime::spawn_stage(recorder, policy.clone()));
+    }
     tethers.push(reducer.spawn(policy.clone()));
     tethers.push(storage.spawn(policy));
 
@@ -89,42 +118,71 @@ pub fn run(args: &Args) -> Result<(), Error> {
 
     info!("Starting daemon...");
 
-    let config = ConfigRoot::new(&args.config).map_err(Error::config)?;
-
-    let current_dir = std::env::current_dir().unwrap();
-    let chain = config.chain.unwrap_or_default();
-    let intersect = config.intersect;
-    let finalize = config.finalize;
-    let storage_type = config.storage.get_type().to_owned();
+    // set when a stage asks for the pipeline to start over from a given point
+    let mut rewind: Option<Restart> = None;
+
+    loop {
+        let config = ConfigRoot::new(&args.config).map_err(Error::config)?;
+
+        let current_dir = std::env::current_dir().unwrap();
+        let chain = config.chain.unwrap_or_default();
+        let finalize = config.finalize;
+        let storage_type = config.storage.get_type().to_owned();
+        let source_type = config.source.get_type().to_owned();
+
+        let (intersect, cursor, undo) = match rewind.take() {
+            Some(restart) => {
+                info!("Rewinding to {:?}", restart.from);
+                (restart.from, Breadcrumbs::new(), restart.undo)
+            }
+            None => (
+                config.intersect,
+                load_cursor_sync(&config.storage).unwrap(),
+                vec![],
+            ),
+        };
+
+        if cursor.is_empty() {
+            info!("No cursor found");
+        } else {
+            info!("Cursor found: {:?}", cursor.latest_known_point().unwrap());
+        }
 
-    let cursor = load_cursor_sync(&config.storage).unwrap();
+        let restart = RestartSignal::default();
 
-    if cursor.is_empty() {
-        info!("No cursor found");
-    } else {
-        info!("Cursor found: {:?}", cursor.latest_known_point().unwrap());
-    }
+        let ctx = Context {
+            current_dir,
+            chain,
+            intersect,
+            cursor,
+            finalize,
+            storage_type,
+            source_type,
+            restart: restart.clone(),
+            undo,
+        };
 
-    let ctx = Context {
-        current_dir,
-        chain,
-        intersect,
-        cursor,
-        finalize,
-        storage_type,
-    };
+        let source = config.source.bootstrapper(&ctx)?;
+        let recorder = config.recorder.map(|x| x.bootstrapper(&ctx)).transpose()?;
+        let reducer = config.reducer.bootstrapper(&ctx)?;
+        let storage = config.storage.bootstrapper(&ctx)?;
 
-    let source = config.source.bootstrapper(&ctx)?;
-    let reducer = config.reducer.bootstrapper(&ctx)?;
-    let storage = config.storage.bootstrapper(&ctx)?;
+        let retries = define_gasket_policy(config.retries.as_ref());
 
-    let retries = define_gasket_policy(config.retries.as_ref());
+        let daemon = connect_stages(source, recorder, reducer, storage, retries)?;
 
-    let daemon = connect_stages(source, reducer, storage, retries)?;
+        info!("lyra is running...");
 
-    info!("lyra is running...");
+        daemon.block();
 
-    daemon.block();
+        match restart.take() {
+            Some(x) => {
+                info!("lyra is restarting");
+                rewind = Some(x);
+            }
+            None => break,
+        }
+    }
 
     info!("lyra is stopping");
 
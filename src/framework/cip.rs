@@ -0,0 +1,267 @@
+//! Parsers for the token metadata standards, shared by the builtin and the
+//! scripted reducers.
+
+use pallas::ledger::primitives::babbage::{BigInt, PlutusData};
+use pallas::ledger::primitives::ToCanonicalJson;
+use serde_json::json;
+use utxorpc_spec::utxorpc::v1alpha::cardano as u5c;
+
+use super::errors::Error;
+
+/// CIP-25 metadata fields that may be split into a list of strings when
+/// longer than 64 bytes
+const CIP25_SPLIT_FIELDS: [&str; 3] = ["image", "description", "src"];
+
+fn metadatum_key(key: &u5c::Metadatum) -> Option<String> {
+    match &key.metadatum {
+        Some(u5c::metadatum::Metadatum::Text(x)) => Some(x.clone()),
+        Some(u5c::metadatum::Metadatum::Bytes(x)) => Some(hex::encode(x)),
+        Some(u5c::metadatum::Metadatum::Int(x)) => Some(x.to_string()),
+        _ => None,
+    }
+}
+
+/// Plain json out of an UTxO RPC metadatum, bytes are hex encoded.
+pub fn metadatum_to_json(value: &u5c::Metadatum) -> serde_json::Value {
+    match &value.metadatum {
+        Some(u5c::metadatum::Metadatum::Int(x)) => json!(x),
+        Some(u5c::metadatum::Metadatum::Bytes(x)) => json!(hex::encode(x)),
+        Some(u5c::metadatum::Metadatum::Text(x)) => json!(x),
+        Some(u5c::metadatum::Metadatum::Array(x)) => {
+            x.items.iter().map(metadatum_to_json).collect()
+        }
+        Some(u5c::metadatum::Metadatum::Map(x)) => x
+            .pairs
+            .iter()
+            .filter_map(|pair| {
+                let key = pair.key.as_ref().and_then(metadatum_key)?;
+                let value = pair.value.as_ref().map(metadatum_to_json)?;
+                Some((key, value))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        None => serde_json::Value::Null,
+    }
+}
+
+/// joins the strings of fields that CIP-25 allows to be split in chunks
+fn join_split_fields(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let chunks = match value {
+                    serde_json::Value::Array(x) if CIP25_SPLIT_FIELDS.contains(&key.as_str()) => {
+                        x.iter().map(|x| x.as_str()).collect::<Option<Vec<_>>>()
+                    }
+                    _ => None,
+                };
+
+                match chunks {
+                    Some(chunks) => *value = json!(chunks.concat()),
+                    None => join_split_fields(value),
+                }
+            }
+        }
+        serde_json::Value::Array(x) => x.iter_mut().for_each(join_split_fields),
+        _ => (),
+    }
+}
+
+/// Flattens the assets of a CIP-25 (label 721) metadatum into a list.
+pub fn parse_cip25(value: &u5c::Metadatum) -> Result<serde_json::Value, Error> {
+    let policies = match &value.metadatum {
+        Some(u5c::metadatum::Metadatum::Map(x)) => x,
+        _ => return Err(Error::parsing("CIP-25 metadata must be a map")),
+    };
+
+    let version = policies
+        .pairs
+        .iter()
+        .find(|x| x.key.as_ref().and_then(metadatum_key).as_deref() == Some("version"))
+        .and_then(|x| x.value.as_ref())
+        .map(metadatum_to_json)
+        .unwrap_or(json!(1));
+
+    let mut assets = vec![];
+
+    for policy in policies.pairs.iter() {
+        // v1 uses text keys (hex policy ids and utf-8 asset names), v2 uses bytes
+        let policy_id = match policy.key.as_ref().map(|x| &x.metadatum) {
+            Some(Some(u5c::metadatum::Metadatum::Text(x))) if x == "version" => continue,
+            Some(Some(u5c::metadatum::Metadatum::Text(x))) => x.clone(),
+            Some(Some(u5c::metadatum::Metadatum::Bytes(x))) => hex::encode(x),
+            _ => continue,
+        };
+
+        let names = match policy.value.as_ref().map(|x| &x.metadatum) {
+            Some(Some(u5c::metadatum::Metadatum::Map(x))) => x,
+            _ => continue,
+        };
+
+        for asset in names.pairs.iter() {
+            let asset_name = match asset.key.as_ref().map(|x| &x.metadatum) {
+                Some(Some(u5c::metadatum::Metadatum::Text(x))) => hex::encode(x.as_bytes()),
+                Some(Some(u5c::metadatum::Metadatum::Bytes(x))) => hex::encode(x),
+                _ => continue,
+            };
+
+            let mut metadata = asset
+                .value
+                .as_ref()
+                .map(metadatum_to_json)
+                .unwrap_or_default();
+
+            join_split_fields(&mut metadata);
+
+            assets.push(json!({
+                "policy_id": policy_id,
+                "asset_name": asset_name,
+                "metadata": metadata,
+            }));
+        }
+    }
+
+    Ok(json!({ "version": version, "assets": assets }))
+}
+
+/// Plain json out of Plutus data, bytes are decoded as utf-8 when possible
+/// since that's how CIP-68 encodes its text fields.
+fn plutus_to_metadata_json(data: &PlutusData) -> serde_json::Value {
+    match data {
+        PlutusData::BoundedBytes(x) => match std::str::from_utf8(x) {
+            Ok(text) => json!(text),
+            Err(_) => json!(hex::encode(x.as_slice())),
+        },
+        PlutusData::BigInt(BigInt::Int(x)) => {
+            let x = i128::from(*x);
+            i64::try_from(x)
+                .map(|x| json!(x))
+                .unwrap_or_else(|_| json!(x.to_string()))
+        }
+        PlutusData::BigInt(_) => data.to_json(),
+        PlutusData::Array(x) => x.iter().map(plutus_to_metadata_json).collect(),
+        PlutusData::Map(x) => x
+            .iter()
+            .map(|(k, v)| {
+                let key = match plutus_to_metadata_json(k) {
+                    serde_json::Value::String(x) => x,
+                    other => other.to_string(),
+                };
+
+                (key, plutus_to_metadata_json(v))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        PlutusData::Constr(_) => data.to_json(),
+    }
+}
+
+/// Parses the datum of a CIP-68 reference token: `Constr 0 [metadata, version, extra]`.
+pub fn parse_cip68(data: &PlutusData) -> Result<serde_json::Value, Error> {
+    let fields = match data {
+        PlutusData::Constr(x) if x.tag == 121 => &x.fields,
+        _ => return Err(Error::parsing("CIP-68 datum must be constructor 0")),
+    };
+
+    let metadata = match fields.first() {
+        Some(x @ PlutusData::Map(_)) => plutus_to_metadata_json(x),
+        _ => return Err(Error::parsing("CIP-68 metadata must be a map")),
+    };
+
+    let version = match fields.get(1) {
+        Some(PlutusData::BigInt(BigInt::Int(x))) => i128::from(*x),
+        _ => return Err(Error::parsing("CIP-68 version must be an integer")),
+    };
+
+    let extra = fields.get(2).map(|x| x.to_json());
+
+    Ok(json!({
+        "metadata": metadata,
+        "version": version as i64,
+        "extra": extra,
+    }))
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// The CIP-67 label prefixed to an asset name, if it has a valid one.
+pub fn cip67_label(asset_name: &[u8]) -> Option<u16> {
+    let prefix = u32::from_be_bytes(asset_name.get(..4)?.try_into().ok()?);
+
+    if prefix >> 28 != 0 || prefix & 0xf != 0 {
+        return None;
+    }
+
+    let label = (prefix >> 12) as u16;
+    let checksum = (prefix >> 4) as u8;
+
+    (crc8(&label.to_be_bytes()) == checksum).then_some(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(name: &str) -> Option<u16> {
+        cip67_label(&hex::decode(name).unwrap())
+    }
+
+    #[test]
+    fn crc8_of_labels() {
+        // the checksums of the CIP-67 test vectors
+        assert_eq!(crc8(&0u16.to_be_bytes()), 0x00);
+        assert_eq!(crc8(&1u16.to_be_bytes()), 0x07);
+        assert_eq!(crc8(&23u16.to_be_bytes()), 0x65);
+        assert_eq!(crc8(&99u16.to_be_bytes()), 0x2e);
+        assert_eq!(crc8(&533u16.to_be_bytes()), 0x41);
+        assert_eq!(crc8(&2000u16.to_be_bytes()), 0x55);
+        assert_eq!(crc8(&65535u16.to_be_bytes()), 0x24);
+    }
+
+    #[test]
+    fn cip67_test_vectors() {
+        assert_eq!(label("00000000"), Some(0));
+        assert_eq!(label("00001070"), Some(1));
+        assert_eq!(label("00017650"), Some(23));
+        assert_eq!(label("000632e0"), Some(99));
+        assert_eq!(label("00215410"), Some(533));
+        assert_eq!(label("007d0550"), Some(2000));
+        assert_eq!(label("0ffff240"), Some(65535));
+    }
+
+    #[test]
+    fn cip68_labels_with_names() {
+        assert_eq!(label("000643b04e4654"), Some(100));
+        assert_eq!(label("000de1404e4654"), Some(222));
+        assert_eq!(label("0014df104e4654"), Some(333));
+        assert_eq!(label("001bc2804e4654"), Some(444));
+    }
+
+    #[test]
+    fn invalid_labels() {
+        // too short
+        assert_eq!(label(""), None);
+        assert_eq!(label("000de1"), None);
+
+        // bad checksum
+        assert_eq!(label("000de150"), None);
+
+        // missing brackets
+        assert_eq!(label("100de140"), None);
+        assert_eq!(label("000de141"), None);
+
+        // plain asset names
+        assert_eq!(label("4e4654"), None);
+        assert_eq!(label("4e465431"), None);
+    }
+}
@@ -37,6 +37,9 @@ pub enum Error {
     #[error("storage error: {0}")]
     StorageError(String),
 
+    #[error("reducer error: {0}")]
+    ReducerError(String),
+
     #[error("chain-sync intersect not found")]
     IntersectNotFound,
 
@@ -96,6 +99,10 @@ impl Error {
         Error::StorageError(error.to_string())
     }
 
+    pub fn reducer(error: impl Display) -> Error {
+        Error::ReducerError(error.to_string())
+    }
+
     pub fn custom(error: Box<dyn std::error::Error>) -> Error {
         Error::Custom(format!("{}", error))
     }
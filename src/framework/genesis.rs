@@ -0,0 +1,334 @@
+use std::path::{Path, PathBuf};
+
+use pallas::ledger::traverse::wellknown::GenesisValues;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use super::errors::Error;
+use super::ChainValues;
+
+/// Genesis files of a custom (private / devnet) chain from which the
+/// chain-level values are derived instead of being written by hand.
+#[derive(Deserialize, Clone)]
+pub struct GenesisFiles {
+    pub byron: Option<PathBuf>,
+    pub shelley: PathBuf,
+    pub alonzo: Option<PathBuf>,
+    pub conway: Option<PathBuf>,
+    /// epoch at which the chain hard-forks from Byron to Shelley, only
+    /// meaningful when a Byron genesis is provided.
+    pub shelley_start_epoch: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ByronGenesis {
+    start_time: u64,
+    block_version_data: ByronBlockVersionData,
+    protocol_consts: ByronProtocolConsts,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ByronBlockVersionData {
+    slot_duration: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ByronProtocolConsts {
+    k: u64,
+    protocol_magic: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShelleyGenesis {
+    network_magic: u64,
+    network_id: String,
+    epoch_length: u64,
+    slot_length: f64,
+    system_start: String,
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| Error::config(format!("can't open genesis {}: {}", path.display(), e)))?;
+
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| Error::config(format!("invalid genesis {}: {}", path.display(), e)))
+}
+
+fn to_u32(value: u64, field: &str) -> Result<u32, Error> {
+    u32::try_from(value).map_err(|_| Error::config(format!("genesis {} is out of range", field)))
+}
+
+/// Shelley `slotLength` is in seconds but can be a fraction of one, down to
+/// the millisecond.
+fn slot_length_ms(value: f64) -> Result<u64, Error> {
+    let ms = (value * 1000.0).round();
+
+    if !value.is_finite() || ms < 1.0 || (value * 1000.0 - ms).abs() > 1e-6 {
+        return Err(Error::config(
+            "genesis slotLength must be a positive whole number of milliseconds",
+        ));
+    }
+
+    Ok(ms as u64)
+}
+
+fn is_leap_year(y: i64) -> bool {
+    y % 4 == 0 && (y % 100 != 0 || y % 400 == 0)
+}
+
+fn days_in_month(y: i64, m: i64) -> i64 {
+    match m {
+        2 if is_leap_year(y) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parses an RFC 3339 UTC timestamp (eg: `2022-10-25T00:00:00Z`) into
+/// seconds since the unix epoch.
+fn parse_system_start(value: &str) -> Result<u64, Error> {
+    let invalid = || Error::config(format!("invalid genesis systemStart: {}", value));
+
+    let value = value.strip_suffix('Z').ok_or_else(invalid)?;
+    let (date, time) = value.split_once('T').ok_or_else(invalid)?;
+
+    // fractional seconds don't matter at slot granularity
+    let time = time.split('.').next().ok_or_else(invalid)?;
+
+    let parse = |part: &str| part.parse::<i64>().map_err(|_| invalid());
+
+    let date: Vec<_> = date.split('-').map(parse).collect::<Result<_, _>>()?;
+    let time: Vec<_> = time.split(':').map(parse).collect::<Result<_, _>>()?;
+
+    let (y, m, d) = match date[..] {
+        [y, m, d] if (1..=12).contains(&m) && (1..=days_in_month(y, m)).contains(&d) => (y, m, d),
+        _ => return Err(invalid()),
+    };
+
+    let (hh, mm, ss) = match time[..] {
+        [hh, mm, ss] if (0..24).contains(&hh) && (0..60).contains(&mm) && (0..61).contains(&ss) => {
+            (hh, mm, ss)
+        }
+        _ => return Err(invalid()),
+    };
+
+    // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hh * 3600 + mm * 60 + ss;
+
+    u64::try_from(secs).map_err(|_| invalid())
+}
+
+impl GenesisFiles {
+    pub fn load(&self) -> Result<GenesisValues, Error> {
+        self.load_chain().map(|x| x.genesis)
+    }
+
+    pub fn load_chain(&self) -> Result<ChainValues, Error> {
+        let shelley: ShelleyGenesis = read_json(&self.shelley)?;
+
+        // these eras don't carry any of the values we derive, but we still
+        // validate them so that a broken devnet setup fails early
+        for path in [&self.alonzo, &self.conway].into_iter().flatten() {
+            read_json::<serde_json::Value>(path)?;
+        }
+
+        if shelley.epoch_length == 0 {
+            return Err(Error::config("genesis epochLength must be positive"));
+        }
+
+        let shelley_slot_length_ms = slot_length_ms(shelley.slot_length)?;
+
+        let address_network_id = match shelley.network_id.as_str() {
+            "Mainnet" => 1,
+            "Testnet" => 0,
+            x => return Err(Error::config(format!("unknown genesis networkId: {}", x))),
+        };
+
+        let shelley_epoch_length = to_u32(shelley.epoch_length, "epochLength")?;
+        // genesis values only hold whole seconds, the exact length is kept
+        // in the chain values alongside
+        let shelley_slot_length = to_u32((shelley_slot_length_ms / 1000).max(1), "slotLength")?;
+        let system_start = parse_system_start(&shelley.system_start)?;
+
+        let values = match &self.byron {
+            Some(path) => {
+                let byron: ByronGenesis = read_json(path)?;
+
+                if byron.protocol_consts.protocol_magic != shelley.network_magic {
+                    return Err(Error::config(format!(
+                        "byron protocolMagic {} doesn't match shelley networkMagic {}",
+                        byron.protocol_consts.protocol_magic, shelley.network_magic
+                    )));
+                }
+
+                let slot_duration_ms: u64 = byron
+                    .block_version_data
+                    .slot_duration
+                    .parse()
+                    .map_err(|_| Error::config("invalid genesis slotDuration"))?;
+
+                if slot_duration_ms == 0 || slot_duration_ms % 1000 != 0 {
+                    return Err(Error::config(
+                        "genesis slotDuration must be a whole number of seconds",
+                    ));
+                }
+
+                let byron_slot_length = slot_duration_ms / 1000;
+                let byron_epoch_length = byron.protocol_consts.k * 10;
+
+                let shelley_known_slot =
+                    self.shelley_start_epoch.unwrap_or_default() * byron_epoch_length;
+                let shelley_known_time = byron.start_time + shelley_known_slot * byron_slot_length;
+
+                GenesisValues {
+                    byron_epoch_length: to_u32(byron_epoch_length, "k")?,
+                    byron_slot_length: to_u32(byron_slot_length, "slotDuration")?,
+                    byron_known_slot: 0,
+                    byron_known_hash: String::new(),
+                    byron_known_time: byron.start_time,
+                    shelley_epoch_length,
+                    shelley_slot_length,
+                    shelley_known_slot,
+                    shelley_known_hash: String::new(),
+                    shelley_known_time,
+                    address_network_id,
+                    magic: shelley.network_magic,
+                }
+            }
+            // without a byron genesis the chain starts straight in shelley (or
+            // a later era) from slot 0
+            None if self.shelley_start_epoch.is_some() => {
+                return Err(Error::config(
+                    "shelley_start_epoch requires a byron genesis file",
+                ));
+            }
+            None => GenesisValues {
+                byron_epoch_length: shelley_epoch_length,
+                byron_slot_length: shelley_slot_length,
+                byron_known_slot: 0,
+                byron_known_hash: String::new(),
+                byron_known_time: system_start,
+                shelley_epoch_length,
+                shelley_slot_length,
+                shelley_known_slot: 0,
+                shelley_known_hash: String::new(),
+                shelley_known_time: system_start,
+                address_network_id,
+                magic: shelley.network_magic,
+            },
+        };
+
+        Ok(ChainValues {
+            genesis: values,
+            shelley_slot_length_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_start_of_known_chains() {
+        assert_eq!(parse_system_start("1970-01-01T00:00:00Z").unwrap(), 0);
+        assert_eq!(
+            parse_system_start("2017-09-23T21:44:51Z").unwrap(),
+            1506203091
+        );
+        assert_eq!(
+            parse_system_start("2022-06-01T00:00:00Z").unwrap(),
+            1654041600
+        );
+    }
+
+    #[test]
+    fn system_start_fractional_seconds() {
+        assert_eq!(
+            parse_system_start("2022-06-01T00:00:00.25Z").unwrap(),
+            1654041600
+        );
+    }
+
+    #[test]
+    fn system_start_boundaries() {
+        assert_eq!(
+            parse_system_start("1999-12-31T23:59:59Z").unwrap(),
+            946684799
+        );
+        assert_eq!(
+            parse_system_start("2000-01-01T00:00:00Z").unwrap(),
+            946684800
+        );
+        assert_eq!(
+            parse_system_start("2021-12-31T00:00:00Z").unwrap(),
+            1640908800
+        );
+        assert_eq!(
+            parse_system_start("2021-04-30T00:00:00Z").unwrap(),
+            1619740800
+        );
+
+        assert!(parse_system_start("2021-04-31T00:00:00Z").is_err());
+        assert!(parse_system_start("2021-13-01T00:00:00Z").is_err());
+        assert!(parse_system_start("2021-00-01T00:00:00Z").is_err());
+        assert!(parse_system_start("2021-01-01T24:00:00Z").is_err());
+        assert!(parse_system_start("2021-01-01T00:60:00Z").is_err());
+        assert!(parse_system_start("1969-12-31T23:59:59Z").is_err());
+    }
+
+    #[test]
+    fn system_start_leap_years() {
+        assert_eq!(
+            parse_system_start("2020-02-29T00:00:00Z").unwrap(),
+            1582934400
+        );
+        assert_eq!(
+            parse_system_start("2020-03-01T00:00:00Z").unwrap(),
+            1583020800
+        );
+        assert_eq!(
+            parse_system_start("2000-02-29T00:00:00Z").unwrap(),
+            951782400
+        );
+
+        assert!(parse_system_start("2021-02-29T00:00:00Z").is_err());
+        assert!(parse_system_start("2100-02-29T00:00:00Z").is_err());
+    }
+
+    #[test]
+    fn system_start_malformed() {
+        assert!(parse_system_start("2022-06-01T00:00:00").is_err());
+        assert!(parse_system_start("2022-06-01 00:00:00Z").is_err());
+        assert!(parse_system_start("2022-06-01Z").is_err());
+        assert!(parse_system_start("2022-06T00:00:00Z").is_err());
+        assert!(parse_system_start("2022-06-01T00:00Z").is_err());
+        assert!(parse_system_start("").is_err());
+    }
+
+    #[test]
+    fn slot_lengths() {
+        assert_eq!(slot_length_ms(1.0).unwrap(), 1000);
+        assert_eq!(slot_length_ms(20.0).unwrap(), 20000);
+        assert_eq!(slot_length_ms(0.2).unwrap(), 200);
+        assert_eq!(slot_length_ms(0.1).unwrap(), 100);
+
+        assert!(slot_length_ms(0.0).is_err());
+        assert!(slot_length_ms(-1.0).is_err());
+        assert!(slot_length_ms(0.0005).is_err());
+        assert!(slot_length_ms(f64::NAN).is_err());
+    }
+}
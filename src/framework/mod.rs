@@ -1,12 +1,15 @@
 use std::collections::VecDeque;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use pallas::ledger::traverse::wellknown::GenesisValues;
 use pallas::network::miniprotocols::Point;
 use serde::Deserialize;
 use utxorpc_spec::utxorpc::v1alpha::cardano::Block;
 
+pub mod cip;
 pub mod errors;
+pub mod genesis;
 pub mod model;
 pub mod policies;
 
@@ -17,6 +20,7 @@ use self::model::{BlockContext, CRDTCommand};
 #[derive(Debug, Clone)]
 pub enum Record {
     RawBlockPayload(Vec<u8>),
+    RawTxPayload(u16, Vec<u8>),
     EnrichedBlockPayload(Vec<u8>, BlockContext),
     UtxoRpcBlockPayload(Block),
     CRDTCommand(Vec<CRDTCommand>),
@@ -29,6 +33,12 @@ pub enum ChainEvent {
     Apply(Point, Record),
     Undo(Point, Record),
     Reset(Point),
+    /// a transaction (by hash) entered the mempool of the upstream node
+    MempoolAdd(String, Record),
+    /// a transaction (by hash) left the mempool of the upstream node
+    MempoolRemove(String, Record),
+    /// a transaction (by hash) seen in the mempool was included in a block
+    MempoolConfirm(String, Record),
 }
 
 impl ChainEvent {
@@ -50,11 +60,39 @@ impl ChainEvent {
         }
     }
 
-    pub fn point(&self) -> &Point {
+    pub fn mempool_add(
+        hash: String,
+        record: impl Into<Record>,
+    ) -> gasket::messaging::Message<Self> {
+        gasket::messaging::Message {
+            payload: Self::MempoolAdd(hash, record.into()),
+        }
+    }
+
+    pub fn mempool_remove(
+        hash: String,
+        record: impl Into<Record>,
+    ) -> gasket::messaging::Message<Self> {
+        gasket::messaging::Message {
+            payload: Self::MempoolRemove(hash, record.into()),
+        }
+    }
+
+    pub fn mempool_confirm(
+        hash: String,
+        record: impl Into<Record>,
+    ) -> gasket::messaging::Message<Self> {
+        gasket::messaging::Message {
+            payload: Self::MempoolConfirm(hash, record.into()),
+        }
+    }
+
+    pub fn point(&self) -> Option<&Point> {
         match self {
-            Self::Apply(x, _) => x,
-            Self::Undo(x, _) => x,
-            Self::Reset(x) => x,
+            Self::Apply(x, _) => Some(x),
+            Self::Undo(x, _) => Some(x),
+            Self::Reset(x) => Some(x),
+            _ => None,
         }
     }
 
@@ -62,15 +100,36 @@ impl ChainEvent {
         match self {
             Self::Apply(_, x) => Some(x),
             Self::Undo(_, x) => Some(x),
+            Self::MempoolAdd(_, x) => Some(x),
+            Self::MempoolRemove(_, x) => Some(x),
+            Self::MempoolConfirm(_, x) => Some(x),
             _ => None,
         }
     }
 
+    /// Builds the same kind of event for the same point / tx, but carrying
+    /// a different record. Used by stages that transform the payload.
+    pub fn with_record(&self, record: Record) -> gasket::messaging::Message<Self> {
+        let payload = match self {
+            Self::Apply(p, _) => Self::Apply(p.clone(), record),
+            Self::Undo(p, _) => Self::Undo(p.clone(), record),
+            Self::Reset(p) => Self::Reset(p.clone()),
+            Self::MempoolAdd(h, _) => Self::MempoolAdd(h.clone(), record),
+            Self::MempoolRemove(h, _) => Self::MempoolRemove(h.clone(), record),
+            Self::MempoolConfirm(h, _) => Self::MempoolConfirm(h.clone(), record),
+        };
+
+        gasket::messaging::Message { payload }
+    }
+
     pub fn map_record(self, f: fn(Record) -> Record) -> Self {
         match self {
             Self::Apply(p, x) => Self::Apply(p, f(x)),
             Self::Undo(p, x) => Self::Undo(p, f(x)),
             Self::Reset(x) => Self::Reset(x),
+            Self::MempoolAdd(h, x) => Self::MempoolAdd(h, f(x)),
+            Self::MempoolRemove(h, x) => Self::MempoolRemove(h, f(x)),
+            Self::MempoolConfirm(h, x) => Self::MempoolConfirm(h, f(x)),
         }
     }
 
@@ -79,6 +138,9 @@ impl ChainEvent {
             Self::Apply(p, x) => Self::Apply(p, f(x)?),
             Self::Undo(p, x) => Self::Undo(p, f(x)?),
             Self::Reset(x) => Self::Reset(x),
+            Self::MempoolAdd(h, x) => Self::MempoolAdd(h, f(x)?),
+            Self::MempoolRemove(h, x) => Self::MempoolRemove(h, f(x)?),
+            Self::MempoolConfirm(h, x) => Self::MempoolConfirm(h, f(x)?),
         };
 
         Ok(out)
@@ -98,6 +160,18 @@ impl ChainEvent {
                 .map(|i| Self::Undo(p.clone(), i))
                 .collect(),
             Self::Reset(x) => vec![Self::Reset(x)],
+            Self::MempoolAdd(h, x) => f(x)?
+                .into_iter()
+                .map(|i| Self::MempoolAdd(h.clone(), i))
+                .collect(),
+            Self::MempoolRemove(h, x) => f(x)?
+                .into_iter()
+                .map(|i| Self::MempoolRemove(h.clone(), i))
+                .collect(),
+            Self::MempoolConfirm(h, x) => f(x)?
+                .into_iter()
+                .map(|i| Self::MempoolConfirm(h.clone(), i))
+                .collect(),
         };
 
         Ok(out)
@@ -107,6 +181,8 @@ impl ChainEvent {
 pub type SourceOutputPort = gasket::messaging::OutputPort<ChainEvent>;
 pub type EnrichInputPort = gasket::messaging::InputPort<ChainEvent>;
 pub type EnrichOutputPort = gasket::messaging::OutputPort<ChainEvent>;
+pub type RecorderInputPort = gasket::messaging::InputPort<ChainEvent>;
+pub type RecorderOutputPort = gasket::messaging::OutputPort<ChainEvent>;
 pub type ReducerInputPort = gasket::messaging::InputPort<ChainEvent>;
 pub type ReducerOutputPort = gasket::messaging::OutputPort<ChainEvent>;
 pub type StorageInputPort = gasket::messaging::InputPort<ChainEvent>;
@@ -123,20 +199,66 @@ pub enum ChainConfig {
     PreProd,
     Preview,
     Custom(GenesisValues),
+    Genesis(genesis::GenesisFiles),
 }
 impl Default for ChainConfig {
     fn default() -> Self {
         Self::Mainnet
     }
 }
-impl From<ChainConfig> for GenesisValues {
-    fn from(other: ChainConfig) -> Self {
+impl TryFrom<ChainConfig> for GenesisValues {
+    type Error = Error;
+
+    fn try_from(other: ChainConfig) -> Result<Self, Self::Error> {
+        match other {
+            ChainConfig::Mainnet => Ok(GenesisValues::mainnet()),
+            ChainConfig::Testnet => Ok(GenesisValues::testnet()),
+            ChainConfig::PreProd => Ok(GenesisValues::preprod()),
+            ChainConfig::Preview => Ok(GenesisValues::preview()),
+            ChainConfig::Custom(x) => Ok(x),
+            ChainConfig::Genesis(x) => x.load(),
+        }
+    }
+}
+
+/// The genesis values of the chain, along with the length of its shelley
+/// slots in milliseconds: devnets may use a fraction of a second, which
+/// `GenesisValues` can't hold.
+#[derive(Clone)]
+pub struct ChainValues {
+    pub genesis: GenesisValues,
+    pub shelley_slot_length_ms: u64,
+}
+
+impl ChainValues {
+    /// Unix time of the slot, in seconds
+    pub fn slot_to_wallclock(&self, slot: u64) -> u64 {
+        let genesis = &self.genesis;
+
+        if slot < genesis.shelley_known_slot {
+            return genesis.slot_to_wallclock(slot);
+        }
+
+        let elapsed_ms = (slot - genesis.shelley_known_slot) * self.shelley_slot_length_ms;
+
+        genesis.shelley_known_time + elapsed_ms / 1000
+    }
+}
+
+impl TryFrom<ChainConfig> for ChainValues {
+    type Error = Error;
+
+    fn try_from(other: ChainConfig) -> Result<Self, Self::Error> {
         match other {
-            ChainConfig::Mainnet => GenesisValues::mainnet(),
-            ChainConfig::Testnet => GenesisValues::testnet(),
-            ChainConfig::PreProd => GenesisValues::preprod(),
-            ChainConfig::Preview => GenesisValues::preview(),
-            ChainConfig::Custom(x) => x,
+            ChainConfig::Genesis(x) => x.load_chain(),
+            x => {
+                let genesis: GenesisValues = x.try_into()?;
+
+                Ok(ChainValues {
+                    shelley_slot_length_ms: genesis.shelley_slot_length as u64 * 1000,
+                    genesis,
+                })
+            }
         }
     }
 }
@@ -287,6 +409,29 @@ pub fn should_finalize(config: &Option<FinalizeConfig>, last_point: &Point) -> b
     false
 }
 
+/// Where the pipeline starts over from, along with the undo events that take
+/// the storage back to that point.
+#[derive(Clone)]
+pub struct Restart {
+    pub from: IntersectConfig,
+    pub undo: Vec<ChainEvent>,
+}
+
+/// Lets a stage ask the daemon to stop the pipeline and start it over from
+/// the given intersect, eg: after a reducer has been reloaded.
+#[derive(Clone, Default)]
+pub struct RestartSignal(Arc<Mutex<Option<Restart>>>);
+
+impl RestartSignal {
+    pub fn request(&self, from: IntersectConfig, undo: Vec<ChainEvent>) {
+        *self.0.lock().unwrap() = Some(Restart { from, undo });
+    }
+
+    pub fn take(&self) -> Option<Restart> {
+        self.0.lock().unwrap().take()
+    }
+}
+
 pub struct Context {
     pub current_dir: PathBuf,
     pub chain: ChainConfig,
@@ -294,4 +439,9 @@ pub struct Context {
     pub cursor: Breadcrumbs,
     pub finalize: Option<FinalizeConfig>,
     pub storage_type: String,
+    pub source_type: String,
+    pub restart: RestartSignal,
+    /// undo events left by the previous run of the pipeline, the reducer
+    /// sends them to the storage before any block
+    pub undo: Vec<ChainEvent>,
 }
@@ -2,7 +2,9 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::str::FromStr;
 
+use pallas::interop::utxorpc::{LedgerContext, TxoRef, UtxoMap};
 use pallas::ledger::traverse::Era;
+use pallas::ledger::traverse::MultiEraBlock;
 use pallas::ledger::traverse::MultiEraOutput;
 use pallas::ledger::traverse::MultiEraTx;
 use pallas::ledger::traverse::OutputRef;
@@ -21,6 +23,19 @@ impl BlockContext {
         self.utxos.insert(key.to_string(), (era, cbor));
     }
 
+    /// Imports the outputs produced by the block itself, so that txs
+    /// spending an output of an earlier tx of the same block resolve it.
+    pub fn import_block_outputs(&mut self, block: &MultiEraBlock) {
+        for tx in block.txs() {
+            let hash = tx.hash();
+
+            for (index, output) in tx.produces() {
+                let key = OutputRef::new(hash, index as u64);
+                self.import_ref_output(&key, output.era(), output.encode());
+            }
+        }
+    }
+
     pub fn find_utxo(&self, key: &OutputRef) -> Result<MultiEraOutput, Error> {
         let (era, cbor) = self
             .utxos
@@ -30,6 +45,19 @@ impl BlockContext {
         MultiEraOutput::decode(*era, cbor).map_err(Error::cbor)
     }
 
+    /// Imports an output under the `<tx hash>#<index>` key it was exported
+    /// with by `utxos`.
+    pub fn import_utxo(&mut self, key: String, era: Era, cbor: Vec<u8>) {
+        self.utxos.insert(key, (era, cbor));
+    }
+
+    /// Every known output, by `<tx hash>#<index>`.
+    pub fn utxos(&self) -> impl Iterator<Item = (&String, Era, &Vec<u8>)> {
+        self.utxos
+            .iter()
+            .map(|(key, (era, cbor))| (key, *era, cbor))
+    }
+
     pub fn get_all_keys(&self) -> Vec<String> {
         self.utxos.keys().map(|x| x.clone()).collect()
     }
@@ -54,6 +82,24 @@ impl BlockContext {
     }
 }
 
+/// Lets the UTxO RPC mapper resolve the inputs of a block from the outputs
+/// we already know about.
+impl LedgerContext for BlockContext {
+    fn get_utxos(&self, refs: &[TxoRef]) -> Option<UtxoMap> {
+        let utxos = refs
+            .iter()
+            .filter_map(|(hash, index)| {
+                let key = OutputRef::new(*hash, *index as u64);
+                self.utxos
+                    .get(&key.to_string())
+                    .map(|(era, cbor)| ((*hash, *index), (*era, cbor.clone())))
+            })
+            .collect();
+
+        Some(utxos)
+    }
+}
+
 pub type Set = String;
 pub type Member = String;
 pub type Key = String;
@@ -86,6 +132,15 @@ impl From<serde_json::Value> for Value {
     }
 }
 
+/// How storages keep json values as text: strings as they are, anything
+/// else serialized.
+pub fn json_text(x: &serde_json::Value) -> String {
+    match x {
+        serde_json::Value::String(x) => x.clone(),
+        x => x.to_string(),
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[non_exhaustive]
 pub enum CRDTCommand {
@@ -97,6 +152,9 @@ pub enum CRDTCommand {
     TwoPhaseSetRemove(Set, Member),
     GrowOnlySetAdd(Set, Member),
     LastWriteWins(Key, Value, Timestamp),
+    /// reverts the `LastWriteWins` of the value at that timestamp, bringing
+    /// back the version it replaced
+    LastWriteWinsUndo(Key, Value, Timestamp),
     AnyWriteWins(Key, Value),
     // TODO make sure Value is a generic not stringly typed
     PNCounter(Key, Delta),
@@ -222,6 +280,51 @@ impl CRDTCommand {
         CRDTCommand::HashCounter(key, member, delta)
     }
 
+    /// Namespaces the key (or set) the command writes to.
+    pub fn with_prefix(self, prefix: &str) -> CRDTCommand {
+        let prefixed = |key: String| format!("{}.{}", prefix, key);
+
+        match self {
+            CRDTCommand::SetAdd(s, m) => CRDTCommand::SetAdd(prefixed(s), m),
+            CRDTCommand::SetRemove(s, m) => CRDTCommand::SetRemove(prefixed(s), m),
+            CRDTCommand::SortedSetAdd(s, m, d) => CRDTCommand::SortedSetAdd(prefixed(s), m, d),
+            CRDTCommand::SortedSetRemove(s, m, d) => {
+                CRDTCommand::SortedSetRemove(prefixed(s), m, d)
+            }
+            CRDTCommand::TwoPhaseSetAdd(s, m) => CRDTCommand::TwoPhaseSetAdd(prefixed(s), m),
+            CRDTCommand::TwoPhaseSetRemove(s, m) => CRDTCommand::TwoPhaseSetRemove(prefixed(s), m),
+            CRDTCommand::GrowOnlySetAdd(s, m) => CRDTCommand::GrowOnlySetAdd(prefixed(s), m),
+            CRDTCommand::LastWriteWins(k, v, t) => CRDTCommand::LastWriteWins(prefixed(k), v, t),
+            CRDTCommand::LastWriteWinsUndo(k, v, t) => {
+                CRDTCommand::LastWriteWinsUndo(prefixed(k), v, t)
+            }
+            CRDTCommand::AnyWriteWins(k, v) => CRDTCommand::AnyWriteWins(prefixed(k), v),
+            CRDTCommand::PNCounter(k, d) => CRDTCommand::PNCounter(prefixed(k), d),
+            CRDTCommand::HashCounter(k, m, d) => CRDTCommand::HashCounter(prefixed(k), m, d),
+            CRDTCommand::HashSetValue(k, m, v) => CRDTCommand::HashSetValue(prefixed(k), m, v),
+            CRDTCommand::HashUnsetKey(k, m) => CRDTCommand::HashUnsetKey(prefixed(k), m),
+        }
+    }
+
+    /// The command reverting this one, for the commands that can be reverted
+    /// without knowing what was stored before them (or that the storage keeps
+    /// track of, like the versions of `LastWriteWins`).
+    pub fn invert(self) -> Option<CRDTCommand> {
+        match self {
+            CRDTCommand::SetAdd(s, m) => Some(CRDTCommand::SetRemove(s, m)),
+            CRDTCommand::SetRemove(s, m) => Some(CRDTCommand::SetAdd(s, m)),
+            CRDTCommand::SortedSetAdd(s, m, d) => Some(CRDTCommand::SortedSetRemove(s, m, -d)),
+            CRDTCommand::SortedSetRemove(s, m, d) => Some(CRDTCommand::SortedSetAdd(s, m, -d)),
+            CRDTCommand::PNCounter(k, d) => Some(CRDTCommand::PNCounter(k, -d)),
+            CRDTCommand::HashCounter(k, m, d) => Some(CRDTCommand::HashCounter(k, m, -d)),
+            CRDTCommand::LastWriteWins(k, v, t) => Some(CRDTCommand::LastWriteWinsUndo(k, v, t)),
+            CRDTCommand::LastWriteWinsUndo(k, v, t) => Some(CRDTCommand::LastWriteWins(k, v, t)),
+            // grow-only and two-phase sets can't shrink, and overwritten values
+            // are gone
+            _ => None,
+        }
+    }
+
     pub fn from_json(value: &JsonValue) -> Result<CRDTCommand, String> {
         let obj = value.as_object().ok_or("Expected a JSON object")?;
 
@@ -259,6 +362,12 @@ impl CRDTCommand {
                 let ts = extract_timestamp(obj, "timestamp")?;
                 Ok(CRDTCommand::LastWriteWins(key, value, ts))
             }
+            Some("LastWriteWinsUndo") => {
+                let key = extract_string(obj, "key")?;
+                let value = extract_value(obj, "value")?;
+                let ts = extract_timestamp(obj, "timestamp")?;
+                Ok(CRDTCommand::LastWriteWinsUndo(key, value, ts))
+            }
             Some("PNCounter") => {
                 let key = extract_string(obj, "key")?;
                 let delta = extract_delta(obj, "value")?;
@@ -1,4 +1,5 @@
 pub mod framework;
+pub mod recorder;
 pub mod reducers;
 pub mod sources;
 pub mod storage;
@@ -0,0 +1,137 @@
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gasket::framework::*;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::framework::*;
+use crate::sources::cbor::{to_archive_context, ArchiveContext, ArchiveEntry, ArchivePayload};
+
+fn to_payload(record: &Record) -> Option<(ArchivePayload, ArchiveContext)> {
+    match record {
+        Record::RawBlockPayload(cbor) => Some((
+            ArchivePayload::Cbor(hex::encode(cbor)),
+            ArchiveContext::new(),
+        )),
+        Record::EnrichedBlockPayload(cbor, ctx) => Some((
+            ArchivePayload::Cbor(hex::encode(cbor)),
+            to_archive_context(ctx),
+        )),
+        Record::UtxoRpcBlockPayload(block) => Some((
+            ArchivePayload::Block(Box::new(block.clone())),
+            ArchiveContext::new(),
+        )),
+        _ => None,
+    }
+}
+
+fn to_entry(event: &ChainEvent) -> Option<ArchiveEntry> {
+    match event {
+        ChainEvent::Apply(_, record) => {
+            to_payload(record).map(|(payload, consumed)| ArchiveEntry::Apply { payload, consumed })
+        }
+        ChainEvent::Undo(_, record) => {
+            to_payload(record).map(|(payload, consumed)| ArchiveEntry::Undo { payload, consumed })
+        }
+        ChainEvent::Reset(Point::Origin) => Some(ArchiveEntry::Reset {
+            slot: 0,
+            hash: None,
+        }),
+        ChainEvent::Reset(Point::Specific(slot, hash)) => Some(ArchiveEntry::Reset {
+            slot: *slot,
+            hash: Some(hex::encode(hash)),
+        }),
+        // the archive only replays the chain, mempool events aren't recorded
+        _ => None,
+    }
+}
+
+pub struct Worker {
+    writer: LineWriter<File>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        std::fs::create_dir_all(&stage.config.dir).or_panic()?;
+
+        // one file per session, named so that replaying the whole directory
+        // plays sessions back in the order they were recorded
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .or_panic()?
+            .as_secs();
+
+        let path = PathBuf::from(&stage.config.dir).join(format!("session-{}.jsonl", started));
+
+        info!("recording session to {}", path.display());
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .or_panic()?;
+
+        Ok(Self {
+            writer: LineWriter::new(file),
+        })
+    }
+
+    async fn schedule(
+        &mut self,
+        stage: &mut Stage,
+    ) -> Result<WorkSchedule<ChainEvent>, WorkerError> {
+        let msg = stage.input.recv().await.or_panic()?;
+        Ok(WorkSchedule::Unit(msg.payload))
+    }
+
+    async fn execute(&mut self, unit: &ChainEvent, stage: &mut Stage) -> Result<(), WorkerError> {
+        if let Some(entry) = to_entry(unit) {
+            let line = serde_json::to_string(&entry).or_panic()?;
+            writeln!(self.writer, "{}", line).or_retry()?;
+
+            debug!("recorded event at {:?}", unit.point());
+            stage.ops_count.inc(1);
+        }
+
+        stage.output.send(unit.clone().into()).await.or_panic()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Stage)]
+#[stage(name = "recorder", unit = "ChainEvent", worker = "Worker")]
+pub struct Stage {
+    config: Config,
+
+    pub input: RecorderInputPort,
+    pub output: RecorderOutputPort,
+
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+}
+
+/// Optional tap between the source and the reducer that writes every event
+/// to a `JsonLines` archive, which the cbor source can replay.
+#[derive(Deserialize)]
+pub struct Config {
+    dir: String,
+}
+
+impl Config {
+    pub fn bootstrapper(self, _ctx: &Context) -> Result<Stage, Error> {
+        let stage = Stage {
+            config: self,
+            input: Default::default(),
+            output: Default::default(),
+            ops_count: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
@@ -0,0 +1,93 @@
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::balance_by_address::block_deltas;
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+    /// hex policy ids to track, all of them when empty
+    #[serde(default)]
+    pub policy_ids: Vec<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("asset_holders".to_string()),
+            policy_ids: self.policy_ids,
+            policy: policy.clone(),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+    policy_ids: Vec<String>,
+    policy: RuntimePolicy,
+}
+
+impl Reducer {
+    fn tracks(&self, unit: &str) -> bool {
+        unit != "lovelace"
+            && (self.policy_ids.is_empty() || self.policy_ids.iter().any(|x| unit.starts_with(x)))
+    }
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let deltas = block_deltas(block, ctx, &self.policy, |address| {
+            Some(address.to_string())
+        })?;
+
+        let mut commands = vec![];
+
+        for (address, units) in deltas {
+            for (unit, delta) in units {
+                if delta == 0 || !self.tracks(&unit) {
+                    continue;
+                }
+
+                let delta = match i64::try_from(delta) {
+                    Ok(x) => x,
+                    Err(_) => {
+                        warn!("skipping holder delta of {} {} out of range", address, unit);
+                        continue;
+                    }
+                };
+
+                // holders whose quantity drops to zero leave the set
+                if delta > 0 {
+                    commands.push(CRDTCommand::sorted_set_add(
+                        Some(&self.prefix),
+                        &unit,
+                        address.clone(),
+                        delta,
+                    ));
+                } else {
+                    commands.push(CRDTCommand::sorted_set_remove(
+                        Some(&self.prefix),
+                        &unit,
+                        address.clone(),
+                        delta,
+                    ));
+                }
+            }
+        }
+
+        Ok(commands)
+    }
+}
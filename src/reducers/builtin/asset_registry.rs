@@ -0,0 +1,200 @@
+use std::collections::BTreeSet;
+
+use pallas::codec::utils::CborWrap;
+use pallas::interop::utxorpc::Mapper;
+use pallas::ledger::primitives::babbage::PseudoDatumOption;
+use pallas::ledger::traverse::{MultiEraBlock, MultiEraTx};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::framework::cip::{cip67_label, parse_cip25, parse_cip68};
+use crate::framework::model::{BlockContext, CRDTCommand};
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::{ReducerConfigTrait, ReducerTrait};
+
+const CIP25_LABEL: u64 = 721;
+
+/// CIP-67 label of the reference token holding the CIP-68 metadata
+const CIP68_REFERENCE_LABEL: u16 = 100;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+    /// hex policy ids to track, all of them when empty
+    #[serde(default)]
+    pub policy_ids: Vec<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, _policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("asset_registry".to_string()),
+            policy_ids: self.policy_ids,
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+    policy_ids: Vec<String>,
+}
+
+impl Reducer {
+    fn tracks(&self, policy_id: &str) -> bool {
+        self.policy_ids.is_empty() || self.policy_ids.iter().any(|x| x == policy_id)
+    }
+
+    /// Supply changes and mint history of the assets minted or burned by
+    /// the tx, returning the units it minted.
+    fn reduce_mints(
+        &self,
+        tx: &MultiEraTx,
+        slot: u64,
+        commands: &mut Vec<CRDTCommand>,
+    ) -> BTreeSet<String> {
+        let hash = tx.hash().to_string();
+        let mut minted = BTreeSet::new();
+
+        for policy in tx.mints() {
+            let policy_id = policy.policy().to_string();
+
+            if !self.tracks(&policy_id) {
+                continue;
+            }
+
+            for asset in policy.assets() {
+                let delta = match asset.mint_coin() {
+                    Some(x) => x,
+                    None => continue,
+                };
+
+                let name = hex::encode(asset.name());
+                let unit = format!("{}{}", policy_id, name);
+
+                commands.push(CRDTCommand::hash_counter(
+                    Some(&self.prefix),
+                    &policy_id,
+                    name,
+                    delta,
+                ));
+
+                commands.push(CRDTCommand::sorted_set_add(
+                    Some(&self.prefix),
+                    &format!("{}.mints", unit),
+                    hash.clone(),
+                    slot as i64,
+                ));
+
+                if delta > 0 {
+                    minted.insert(unit);
+                }
+            }
+        }
+
+        minted
+    }
+
+    /// CIP-25 metadata of the assets minted by the tx, metadata of assets
+    /// the tx doesn't mint is ignored.
+    fn reduce_cip25(
+        &self,
+        tx: &MultiEraTx,
+        slot: u64,
+        minted: &BTreeSet<String>,
+        commands: &mut Vec<CRDTCommand>,
+    ) {
+        let metadatum = match tx.metadata().find(CIP25_LABEL) {
+            Some(x) => Mapper::new(BlockContext::default()).map_metadatum(x),
+            None => return,
+        };
+
+        let parsed = match parse_cip25(&metadatum) {
+            Ok(x) => x,
+            Err(err) => {
+                warn!("skipping CIP-25 metadata of tx {}: {}", tx.hash(), err);
+                return;
+            }
+        };
+
+        let assets = parsed["assets"].as_array().cloned().unwrap_or_default();
+
+        for asset in assets {
+            let unit = format!(
+                "{}{}",
+                asset["policy_id"].as_str().unwrap_or_default(),
+                asset["asset_name"].as_str().unwrap_or_default()
+            );
+
+            if minted.contains(&unit) {
+                commands.push(CRDTCommand::last_write_wins(
+                    Some(&self.prefix),
+                    &format!("{}.cip25", unit),
+                    asset["metadata"].to_string(),
+                    slot,
+                ));
+            }
+        }
+    }
+
+    /// CIP-68 metadata held in the inline datum of the outputs carrying a
+    /// reference token.
+    fn reduce_cip68(&self, tx: &MultiEraTx, slot: u64, commands: &mut Vec<CRDTCommand>) {
+        for (_, output) in tx.produces() {
+            let datum = match output.datum() {
+                Some(PseudoDatumOption::Data(CborWrap(x))) => x,
+                _ => continue,
+            };
+
+            for policy in output.non_ada_assets() {
+                let policy_id = policy.policy().to_string();
+
+                if !self.tracks(&policy_id) {
+                    continue;
+                }
+
+                for asset in policy.assets() {
+                    if cip67_label(asset.name()) != Some(CIP68_REFERENCE_LABEL) {
+                        continue;
+                    }
+
+                    let unit = format!("{}{}", policy_id, hex::encode(asset.name()));
+
+                    match parse_cip68(&datum) {
+                        Ok(metadata) => commands.push(CRDTCommand::last_write_wins(
+                            Some(&self.prefix),
+                            &format!("{}.cip68", unit),
+                            metadata.to_string(),
+                            slot,
+                        )),
+                        Err(err) => warn!("skipping CIP-68 metadata of {}: {}", unit, err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        _ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let slot = block.slot();
+        let mut commands = vec![];
+
+        // failed txs don't mint and their outputs are never created
+        for tx in block.txs().iter().filter(|tx| tx.is_valid()) {
+            let minted = self.reduce_mints(tx, slot, &mut commands);
+            self.reduce_cip25(tx, slot, &minted, &mut commands);
+            self.reduce_cip68(tx, slot, &mut commands);
+        }
+
+        Ok(super::last_versions(commands))
+    }
+}
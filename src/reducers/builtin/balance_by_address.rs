@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use pallas::ledger::addresses::Address;
+use pallas::ledger::traverse::{MultiEraBlock, MultiEraOutput};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::{ReducerConfigTrait, ReducerTrait};
+
+/// Net change of each unit (lovelace, or policy id followed by the hex asset
+/// name) held by each owner.
+pub type Deltas = BTreeMap<String, BTreeMap<String, i128>>;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("balance_by_address".to_string()),
+            policy: policy.clone(),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+    policy: RuntimePolicy,
+}
+
+fn add_output(deltas: &mut Deltas, owner: String, output: &MultiEraOutput, sign: i128) {
+    let units = deltas.entry(owner).or_default();
+
+    *units.entry("lovelace".to_string()).or_default() += sign * output.lovelace_amount() as i128;
+
+    for policy in output.non_ada_assets() {
+        for asset in policy.assets() {
+            if let Some(quantity) = asset.output_coin() {
+                let unit = format!("{}{}", policy.policy(), hex::encode(asset.name()));
+                *units.entry(unit).or_default() += sign * quantity as i128;
+            }
+        }
+    }
+}
+
+/// Adds up what the outputs consumed and produced by the block move in and
+/// out of each owner, `owner` maps the address of an output to the key its
+/// balance is grouped by.
+pub fn block_deltas(
+    block: &MultiEraBlock,
+    ctx: &model::BlockContext,
+    policy: &RuntimePolicy,
+    owner: impl Fn(&Address) -> Option<String>,
+) -> Result<Deltas, Error> {
+    let mut deltas = Deltas::new();
+
+    for tx in block.txs() {
+        for (_, consumed) in ctx.find_consumed_txos(&tx, policy)? {
+            if let Some(key) = consumed.address().ok().as_ref().and_then(&owner) {
+                add_output(&mut deltas, key, &consumed, -1);
+            }
+        }
+
+        for (_, produced) in tx.produces() {
+            if let Some(key) = produced.address().ok().as_ref().and_then(&owner) {
+                add_output(&mut deltas, key, &produced, 1);
+            }
+        }
+    }
+
+    Ok(deltas)
+}
+
+/// Per owner, a `<prefix>.<owner>` hash counting every unit it holds, plus a
+/// `<prefix>` sorted set ranking the owners by their lovelace.
+pub fn deltas_to_commands(prefix: &str, deltas: Deltas) -> Vec<CRDTCommand> {
+    let mut commands = vec![];
+
+    for (owner, units) in deltas {
+        for (unit, delta) in units {
+            if delta == 0 {
+                continue;
+            }
+
+            // redis counters are 64 bits, tokens minted close to u64::MAX
+            // can't be tracked
+            let delta = match i64::try_from(delta) {
+                Ok(x) => x,
+                Err(_) => {
+                    warn!("skipping balance delta of {} {} out of range", owner, unit);
+                    continue;
+                }
+            };
+
+            if unit == "lovelace" && delta > 0 {
+                commands.push(CRDTCommand::sorted_set_add(
+                    None,
+                    prefix,
+                    owner.clone(),
+                    delta,
+                ));
+            } else if unit == "lovelace" {
+                commands.push(CRDTCommand::sorted_set_remove(
+                    None,
+                    prefix,
+                    owner.clone(),
+                    delta,
+                ));
+            }
+
+            commands.push(CRDTCommand::hash_counter(Some(prefix), &owner, unit, delta));
+        }
+    }
+
+    commands
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let deltas = block_deltas(block, ctx, &self.policy, |address| {
+            Some(address.to_string())
+        })?;
+
+        Ok(deltas_to_commands(&self.prefix, deltas))
+    }
+}
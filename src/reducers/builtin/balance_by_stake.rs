@@ -0,0 +1,53 @@
+use pallas::ledger::addresses::{Address, StakeAddress};
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::balance_by_address::{block_deltas, deltas_to_commands};
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("balance_by_stake".to_string()),
+            policy: policy.clone(),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+    policy: RuntimePolicy,
+}
+
+/// The bech32 stake address delegated to by `address`. Byron, enterprise and
+/// pointer addresses don't have one.
+fn stake_address(address: &Address) -> Option<String> {
+    match address {
+        Address::Shelley(x) => StakeAddress::try_from(x.clone()).ok()?.to_bech32().ok(),
+        _ => None,
+    }
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let deltas = block_deltas(block, ctx, &self.policy, stake_address)?;
+
+        Ok(deltas_to_commands(&self.prefix, deltas))
+    }
+}
@@ -0,0 +1,225 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use pallas::codec::utils::Nullable;
+use pallas::ledger::addresses::Address;
+use pallas::ledger::primitives::alonzo::{
+    Certificate, PoolMetadata, Relay, StakeCredential, UnitInterval,
+};
+use pallas::ledger::primitives::conway::{Anchor, Certificate as ConwayCertificate, DRep};
+use pallas::ledger::traverse::{MultiEraCert, MultiEraTx};
+use serde_json::json;
+
+/// What a certificate does to the delegation state, the same across eras.
+pub enum CertEvent {
+    Registration(String),
+    Deregistration(String),
+    PoolDelegation(String, String),
+    DRepDelegation(String, serde_json::Value),
+    PoolRegistration(String, serde_json::Value),
+    PoolRetirement(String, u64),
+    DRepRegistration(String, u64, Option<serde_json::Value>),
+    DRepDeregistration(String),
+    DRepUpdate(String, Option<serde_json::Value>),
+}
+
+/// Hex hash of the key or script of a stake credential
+pub fn credential(x: &StakeCredential) -> String {
+    match x {
+        StakeCredential::AddrKeyhash(x) => x.to_string(),
+        StakeCredential::Scripthash(x) => x.to_string(),
+    }
+}
+
+fn drep_json(x: &DRep) -> serde_json::Value {
+    match x {
+        DRep::Key(x) => json!({ "type": "key", "hash": x.to_string() }),
+        DRep::Script(x) => json!({ "type": "script", "hash": x.to_string() }),
+        DRep::Abstain => json!({ "type": "abstain" }),
+        DRep::NoConfidence => json!({ "type": "no_confidence" }),
+    }
+}
+
+pub fn nullable<T: Clone>(x: &Nullable<T>) -> Option<T> {
+    match x {
+        Nullable::Some(x) => Some(x.clone()),
+        _ => None,
+    }
+}
+
+/// Reward accounts are stake addresses, hex when they can't be decoded
+pub fn reward_address(x: &[u8]) -> String {
+    Address::from_bytes(x)
+        .ok()
+        .and_then(|x| x.to_bech32().ok())
+        .unwrap_or_else(|| hex::encode(x))
+}
+
+pub fn anchor_json(x: &Anchor) -> serde_json::Value {
+    json!({ "url": x.url, "hash": x.content_hash.to_string() })
+}
+
+fn relay_json(x: &Relay) -> serde_json::Value {
+    match x {
+        Relay::SingleHostAddr(port, ipv4, ipv6) => json!({
+            "type": "address",
+            "port": nullable(port),
+            "ipv4": nullable(ipv4)
+                .and_then(|x| <[u8; 4]>::try_from(x.as_slice()).ok())
+                .map(|x| Ipv4Addr::from(x).to_string()),
+            "ipv6": nullable(ipv6)
+                .and_then(|x| <[u8; 16]>::try_from(x.as_slice()).ok())
+                .map(|x| Ipv6Addr::from(x).to_string()),
+        }),
+        Relay::SingleHostName(port, name) => json!({
+            "type": "name",
+            "port": nullable(port),
+            "name": name,
+        }),
+        Relay::MultiHostName(name) => json!({
+            "type": "srv",
+            "name": name,
+        }),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn pool_json<'a>(
+    vrf_keyhash: String,
+    pledge: u64,
+    cost: u64,
+    margin: &UnitInterval,
+    reward_account: &[u8],
+    owners: impl Iterator<Item = String>,
+    relays: impl Iterator<Item = &'a Relay>,
+    metadata: &Nullable<PoolMetadata>,
+) -> serde_json::Value {
+    json!({
+        "vrf_keyhash": vrf_keyhash,
+        "pledge": pledge,
+        "cost": cost,
+        "margin": { "numerator": margin.numerator, "denominator": margin.denominator },
+        "reward_account": reward_address(reward_account),
+        "owners": owners.collect::<Vec<_>>(),
+        "relays": relays.map(relay_json).collect::<Vec<_>>(),
+        "metadata": nullable(metadata).map(|x| json!({ "url": x.url, "hash": x.hash.to_string() })),
+    })
+}
+
+fn alonzo_events(cert: &Certificate) -> Vec<CertEvent> {
+    match cert {
+        Certificate::StakeRegistration(x) => vec![CertEvent::Registration(credential(x))],
+        Certificate::StakeDeregistration(x) => vec![CertEvent::Deregistration(credential(x))],
+        Certificate::StakeDelegation(x, pool) => {
+            vec![CertEvent::PoolDelegation(credential(x), pool.to_string())]
+        }
+        Certificate::PoolRegistration {
+            operator,
+            vrf_keyhash,
+            pledge,
+            cost,
+            margin,
+            reward_account,
+            pool_owners,
+            relays,
+            pool_metadata,
+        } => vec![CertEvent::PoolRegistration(
+            operator.to_string(),
+            pool_json(
+                vrf_keyhash.to_string(),
+                *pledge,
+                *cost,
+                margin,
+                reward_account,
+                pool_owners.iter().map(|x| x.to_string()),
+                relays.iter(),
+                pool_metadata,
+            ),
+        )],
+        Certificate::PoolRetirement(pool, epoch) => {
+            vec![CertEvent::PoolRetirement(pool.to_string(), *epoch)]
+        }
+        _ => vec![],
+    }
+}
+
+fn conway_events(cert: &ConwayCertificate) -> Vec<CertEvent> {
+    use ConwayCertificate::*;
+
+    match cert {
+        StakeRegistration(x) | Reg(x, _) => vec![CertEvent::Registration(credential(x))],
+        StakeDeregistration(x) | UnReg(x, _) => vec![CertEvent::Deregistration(credential(x))],
+        StakeDelegation(x, pool) => {
+            vec![CertEvent::PoolDelegation(credential(x), pool.to_string())]
+        }
+        VoteDeleg(x, drep) => vec![CertEvent::DRepDelegation(credential(x), drep_json(drep))],
+        StakeVoteDeleg(x, pool, drep) => vec![
+            CertEvent::PoolDelegation(credential(x), pool.to_string()),
+            CertEvent::DRepDelegation(credential(x), drep_json(drep)),
+        ],
+        StakeRegDeleg(x, pool, _) => vec![
+            CertEvent::Registration(credential(x)),
+            CertEvent::PoolDelegation(credential(x), pool.to_string()),
+        ],
+        VoteRegDeleg(x, drep, _) => vec![
+            CertEvent::Registration(credential(x)),
+            CertEvent::DRepDelegation(credential(x), drep_json(drep)),
+        ],
+        StakeVoteRegDeleg(x, pool, drep, _) => vec![
+            CertEvent::Registration(credential(x)),
+            CertEvent::PoolDelegation(credential(x), pool.to_string()),
+            CertEvent::DRepDelegation(credential(x), drep_json(drep)),
+        ],
+        PoolRegistration {
+            operator,
+            vrf_keyhash,
+            pledge,
+            cost,
+            margin,
+            reward_account,
+            pool_owners,
+            relays,
+            pool_metadata,
+        } => vec![CertEvent::PoolRegistration(
+            operator.to_string(),
+            pool_json(
+                vrf_keyhash.to_string(),
+                *pledge,
+                *cost,
+                margin,
+                reward_account,
+                pool_owners.iter().map(|x| x.to_string()),
+                relays.iter(),
+                pool_metadata,
+            ),
+        )],
+        PoolRetirement(pool, epoch) => vec![CertEvent::PoolRetirement(pool.to_string(), *epoch)],
+        RegDRepCert(x, deposit, anchor) => vec![CertEvent::DRepRegistration(
+            credential(x),
+            *deposit,
+            nullable(anchor).as_ref().map(anchor_json),
+        )],
+        UnRegDRepCert(x, _) => vec![CertEvent::DRepDeregistration(credential(x))],
+        UpdateDRepCert(x, anchor) => vec![CertEvent::DRepUpdate(
+            credential(x),
+            nullable(anchor).as_ref().map(anchor_json),
+        )],
+        _ => vec![],
+    }
+}
+
+/// The certificate events of a tx, in order. Certificates of failed txs
+/// never take effect.
+pub fn tx_events(tx: &MultiEraTx) -> Vec<CertEvent> {
+    if !tx.is_valid() {
+        return vec![];
+    }
+
+    tx.certs()
+        .iter()
+        .flat_map(|cert| match cert {
+            MultiEraCert::AlonzoCompatible(x) => alonzo_events(x),
+            MultiEraCert::Conway(x) => conway_events(x),
+            _ => vec![],
+        })
+        .collect()
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+
+use pallas::codec::minicbor;
+use pallas::crypto::hash::{Hash, Hasher};
+use pallas::ledger::primitives::babbage::PseudoDatumOption;
+use pallas::ledger::traverse::{MultiEraBlock, MultiEraTx, OriginalHash};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::full_utxos_by_address::{decode_script_ref, resolve_datum};
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, _policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("datums_and_scripts".to_string()),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+}
+
+/// Datums and scripts carried by a tx, by hash, along with the outputs
+/// referencing them
+#[derive(Default)]
+struct Carried {
+    datums: BTreeMap<Hash<32>, Vec<u8>>,
+    scripts: BTreeMap<Hash<28>, (&'static str, Vec<u8>)>,
+    datum_refs: Vec<(Hash<32>, String)>,
+    script_refs: Vec<(Hash<28>, String)>,
+}
+
+impl Carried {
+    fn add_script(&mut self, kind: &'static str, tag: u8, script: &[u8]) {
+        let hash = Hasher::<224>::hash_tagged(script, tag);
+        self.scripts.insert(hash, (kind, script.to_vec()));
+    }
+}
+
+fn tx_carried(tx: &MultiEraTx) -> Carried {
+    let mut carried = Carried::default();
+
+    for datum in tx.plutus_data() {
+        carried
+            .datums
+            .insert(datum.original_hash(), datum.raw_cbor().to_vec());
+    }
+
+    for script in tx.native_scripts() {
+        carried.add_script("native", 0, script.raw_cbor());
+    }
+
+    for script in tx.plutus_v1_scripts() {
+        carried.add_script("plutus_v1", 1, &script.0);
+    }
+
+    for script in tx.plutus_v2_scripts() {
+        carried.add_script("plutus_v2", 2, &script.0);
+    }
+
+    for script in tx.plutus_v3_scripts() {
+        carried.add_script("plutus_v3", 3, &script.0);
+    }
+
+    let hash = tx.hash();
+
+    for (index, output) in tx.produces() {
+        let output_ref = format!("{}#{}", hash, index);
+
+        // outputs holding a datum hash keep the reference even when the
+        // datum itself isn't witnessed
+        match output.datum() {
+            Some(PseudoDatumOption::Data(x)) => carried
+                .datum_refs
+                .push((x.original_hash(), output_ref.clone())),
+            Some(PseudoDatumOption::Hash(x)) => carried.datum_refs.push((x, output_ref.clone())),
+            None => (),
+        }
+
+        if let Some((hash, cbor)) = resolve_datum(&output, tx) {
+            carried.datums.insert(hash, cbor);
+        }
+
+        let script = output.script_ref().and_then(|x| minicbor::to_vec(&x).ok());
+
+        if let Some(cbor) = script {
+            if let Some((kind, hash, script)) = decode_script_ref(&cbor) {
+                carried.scripts.insert(hash, (kind, script.to_vec()));
+                carried.script_refs.push((hash, output_ref));
+            }
+        }
+    }
+
+    carried
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        _ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let prefix = Some(self.prefix.as_str());
+        let mut commands = vec![];
+
+        for tx in block.txs().iter().filter(|tx| tx.is_valid()) {
+            let carried = tx_carried(tx);
+
+            // the content of a hash never changes, the sets hold a single
+            // member scored by the number of txs carrying it
+            for (hash, cbor) in carried.datums {
+                commands.push(CRDTCommand::sorted_set_add(
+                    prefix,
+                    &format!("datum.{}", hash),
+                    hex::encode(cbor),
+                    1,
+                ));
+            }
+
+            for (hash, (kind, script)) in carried.scripts {
+                let script = json!({ "type": kind, "script": hex::encode(script) });
+
+                commands.push(CRDTCommand::sorted_set_add(
+                    prefix,
+                    &format!("script.{}", hash),
+                    script.to_string(),
+                    1,
+                ));
+            }
+
+            for (hash, output_ref) in carried.datum_refs {
+                commands.push(CRDTCommand::set_add(
+                    prefix,
+                    &format!("datum.{}.outputs", hash),
+                    output_ref,
+                ));
+            }
+
+            for (hash, output_ref) in carried.script_refs {
+                commands.push(CRDTCommand::set_add(
+                    prefix,
+                    &format!("script.{}.outputs", hash),
+                    output_ref,
+                ));
+            }
+        }
+
+        Ok(commands)
+    }
+}
@@ -0,0 +1,98 @@
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::certificates::{tx_events, CertEvent};
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, _policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("drep_registry".to_string()),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+}
+
+impl Reducer {
+    /// a new version of `<prefix>.<drep>.<field>`
+    fn version(
+        &self,
+        drep: &str,
+        field: &str,
+        value: serde_json::Value,
+        tx: &str,
+        slot: u64,
+    ) -> CRDTCommand {
+        let mut version = json!({ "tx": tx });
+        version[field] = value;
+
+        CRDTCommand::last_write_wins(
+            Some(&self.prefix),
+            &format!("{}.{}", drep, field),
+            version.to_string(),
+            slot,
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        _ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let slot = block.slot();
+        let mut commands = vec![];
+
+        for tx in block.txs() {
+            let hash = tx.hash().to_string();
+
+            for event in tx_events(&tx) {
+                match event {
+                    CertEvent::DRepRegistration(x, deposit, anchor) => {
+                        commands.push(CRDTCommand::sorted_set_add(
+                            None,
+                            &self.prefix,
+                            x.clone(),
+                            1,
+                        ));
+                        commands.push(self.version(&x, "deposit", json!(deposit), &hash, slot));
+                        commands.push(self.version(&x, "anchor", json!(anchor), &hash, slot));
+                    }
+                    CertEvent::DRepDeregistration(x) => {
+                        commands.push(CRDTCommand::sorted_set_remove(
+                            None,
+                            &self.prefix,
+                            x.clone(),
+                            -1,
+                        ));
+                        commands.push(self.version(&x, "deposit", json!(null), &hash, slot));
+                        commands.push(self.version(&x, "anchor", json!(null), &hash, slot));
+                    }
+                    CertEvent::DRepUpdate(x, anchor) => {
+                        commands.push(self.version(&x, "anchor", json!(anchor), &hash, slot));
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(super::last_versions(commands))
+    }
+}
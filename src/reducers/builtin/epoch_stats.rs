@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+
+use pallas::crypto::hash::Hasher;
+use pallas::ledger::traverse::wellknown::GenesisValues;
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::ReducerTrait;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+}
+
+impl Config {
+    /// Unlike the other reducers, epoch boundaries depend on the chain
+    pub fn plugin(self, policy: &RuntimePolicy, chain: &GenesisValues) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("epoch_stats".to_string()),
+            policy: policy.clone(),
+            chain: chain.clone(),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+    policy: RuntimePolicy,
+    chain: GenesisValues,
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let (epoch, _) = self.chain.absolute_slot_to_relative(block.slot());
+        let key = epoch.to_string();
+        let prefix = Some(self.prefix.as_str());
+
+        let mut txs = 0;
+        let mut fees = 0;
+        let mut volume = 0;
+        let mut addresses = BTreeSet::new();
+
+        for tx in block.txs() {
+            // the collateral of failed txs, since that's what they consume
+            let consumed: Vec<_> = ctx
+                .find_consumed_txos(&tx, &self.policy)?
+                .into_iter()
+                .map(|(_, output)| output)
+                .collect();
+
+            let produced: Vec<_> = tx
+                .produces()
+                .into_iter()
+                .map(|(_, output)| output)
+                .collect();
+
+            for output in consumed.iter().chain(produced.iter()) {
+                if let Ok(address) = output.address() {
+                    addresses.insert(address.to_string());
+                }
+            }
+
+            txs += 1;
+
+            // failed txs pay with their collateral instead: the declared total
+            // or else whatever the collateral return doesn't give back
+            if tx.is_valid() {
+                fees += tx.fee().unwrap_or_default();
+                volume += produced.iter().map(|x| x.lovelace_amount()).sum::<u64>();
+            } else {
+                fees += match tx.total_collateral() {
+                    Some(x) => x,
+                    None => {
+                        let collateral: u64 = consumed.iter().map(|x| x.lovelace_amount()).sum();
+                        let returned = tx
+                            .collateral_return()
+                            .map(|x| x.lovelace_amount())
+                            .unwrap_or_default();
+
+                        collateral.saturating_sub(returned)
+                    }
+                };
+            }
+        }
+
+        let mut commands = vec![
+            CRDTCommand::hash_counter(prefix, &key, "blocks".to_string(), 1),
+            CRDTCommand::hash_counter(prefix, &key, "txs".to_string(), txs),
+            CRDTCommand::hash_counter(prefix, &key, "fees".to_string(), fees as i64),
+            CRDTCommand::hash_counter(prefix, &key, "output_volume".to_string(), volume as i64),
+        ];
+
+        // byron blocks aren't minted by pools
+        if let Some(issuer) = block.header().issuer_vkey() {
+            let pool = Hasher::<224>::hash(issuer).to_string();
+
+            commands.push(CRDTCommand::hash_counter(
+                prefix,
+                &format!("{}.pools", key),
+                pool,
+                1,
+            ));
+        }
+
+        // addresses are counted once per block they show up in, so rolling a
+        // block back keeps the ones other blocks of the epoch touched
+        for address in addresses {
+            commands.push(CRDTCommand::sorted_set_add(
+                prefix,
+                &format!("{}.addresses", key),
+                address,
+                1,
+            ));
+        }
+
+        Ok(commands)
+    }
+}
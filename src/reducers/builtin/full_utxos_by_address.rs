@@ -1,113 +1,226 @@
+use pallas::codec::minicbor;
 use pallas::codec::utils::CborWrap;
-use pallas::crypto::hash::Hash;
-use pallas::ledger::primitives::babbage::{PlutusData, PseudoDatumOption};
-use pallas::ledger::primitives::Fragment;
-use pallas::ledger::traverse::{
-    MultiEraBlock, MultiEraOutput, MultiEraPolicyAssets, MultiEraTx, OriginalHash,
-};
+use pallas::crypto::hash::{Hash, Hasher};
+use pallas::ledger::addresses::Address;
+use pallas::ledger::primitives::babbage::PseudoDatumOption;
+use pallas::ledger::traverse::{MultiEraBlock, MultiEraOutput, MultiEraTx, OriginalHash};
 use serde::Deserialize;
 use serde_json::json;
 
 use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
 use crate::framework::{model, Error};
 
 use super::{ReducerConfigTrait, ReducerTrait};
 
+/// Outputs are tracked when they match any of the filters, none of them when
+/// no filter is set.
 #[derive(Deserialize)]
 pub struct Config {
+    /// bech32 (or base58 for byron) addresses
+    #[serde(default)]
     pub filter: Vec<String>,
+    /// hex hashes of payment key or script credentials, or bech32 addresses
+    /// to take the payment part of
+    #[serde(default)]
+    pub payment_credentials: Vec<String>,
+    /// hex hashes of stake key or script credentials, or bech32 stake
+    /// addresses
+    #[serde(default)]
+    pub stake_credentials: Vec<String>,
+    /// hex policy ids of assets held by the outputs
+    #[serde(default)]
+    pub policy_ids: Vec<String>,
     pub prefix: Option<String>,
     pub address_as_key: Option<bool>,
 }
+
+/// Hex hashes are matched lowercase and without a `0x` prefix
+fn normalize_hex(x: &str) -> String {
+    let x = x.trim().to_lowercase();
+
+    match x.strip_prefix("0x") {
+        Some(x) => x.to_string(),
+        None => x,
+    }
+}
+
 impl ReducerConfigTrait for Config {
-    fn plugin(self) -> Box<dyn ReducerTrait> {
-        let reducer = Reducer { config: self };
+    fn plugin(self, _policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let payment_credentials = self
+            .payment_credentials
+            .iter()
+            .map(|x| match Address::from_bech32(x.trim()) {
+                Ok(Address::Shelley(shelley)) => shelley.payment().as_hash().to_string(),
+                _ => normalize_hex(x),
+            })
+            .collect();
+
+        let stake_credentials = self
+            .stake_credentials
+            .iter()
+            .map(|x| match Address::from_bech32(x.trim()) {
+                Ok(Address::Stake(stake)) => stake.payload().as_hash().to_string(),
+                _ => normalize_hex(x),
+            })
+            .collect();
+
+        let policy_ids = self.policy_ids.iter().map(|x| normalize_hex(x)).collect();
+
+        let reducer = Reducer {
+            payment_credentials,
+            stake_credentials,
+            policy_ids,
+            config: self,
+        };
+
         Box::new(reducer)
     }
 }
 
 pub struct Reducer {
     config: Config,
+    /// the configured credentials and policies, normalized to hex hashes
+    payment_credentials: Vec<String>,
+    stake_credentials: Vec<String>,
+    policy_ids: Vec<String>,
 }
 
-pub fn resolve_datum(utxo: &MultiEraOutput, tx: &MultiEraTx) -> Option<PlutusData> {
-    if let Some(datum) = utxo.datum() {
-        return match datum {
-            PseudoDatumOption::Data(CborWrap(pd)) => Some(pd.unwrap()),
-            PseudoDatumOption::Hash(datum_hash) => {
-                for raw_datum in tx.clone().plutus_data() {
-                    if raw_datum.original_hash().eq(&datum_hash) {
-                        return Some(raw_datum.clone().unwrap());
-                    }
-                }
+/// Language, hash and bytes of a reference script, from the cbor of its
+/// `[language, script]` envelope.
+pub fn decode_script_ref(cbor: &[u8]) -> Option<(&'static str, Hash<28>, &[u8])> {
+    let mut decoder = minicbor::Decoder::new(cbor);
+    decoder.array().ok()?;
+    let tag = decoder.u8().ok()?;
 
-                return None;
-            }
-        };
+    // native scripts hash their cbor, plutus scripts the bytes they're wrapped in
+    let (kind, script) = match tag {
+        0 => ("native", &cbor[decoder.position()..]),
+        1 => ("plutus_v1", decoder.bytes().ok()?),
+        2 => ("plutus_v2", decoder.bytes().ok()?),
+        3 => ("plutus_v3", decoder.bytes().ok()?),
+        _ => return None,
+    };
+
+    Some((kind, Hasher::<224>::hash_tagged(script, tag), script))
+}
+
+/// The datum held by an output, inline or witnessed by the tx when the
+/// output only holds its hash, as its hash and cbor.
+pub fn resolve_datum(utxo: &MultiEraOutput, tx: &MultiEraTx) -> Option<(Hash<32>, Vec<u8>)> {
+    match utxo.datum()? {
+        PseudoDatumOption::Data(CborWrap(x)) => Some((x.original_hash(), x.raw_cbor().to_vec())),
+        PseudoDatumOption::Hash(hash) => tx
+            .plutus_data()
+            .iter()
+            .find(|x| x.original_hash() == hash)
+            .map(|x| (hash, x.raw_cbor().to_vec())),
     }
+}
 
-    None
+/// The datum of an output holding only its hash, when the producing tx
+/// witnesses it.
+fn witnessed_datum(utxo: &MultiEraOutput, tx: &MultiEraTx) -> Option<(Hash<32>, Vec<u8>)> {
+    match utxo.datum()? {
+        PseudoDatumOption::Hash(_) => resolve_datum(utxo, tx),
+        _ => None,
+    }
 }
 
 impl Reducer {
+    fn matches(&self, utxo: &MultiEraOutput, address: &Address) -> bool {
+        if self.config.filter.contains(&address.to_string()) {
+            return true;
+        }
+
+        if let Address::Shelley(shelley) = address {
+            let payment = shelley.payment().as_hash().to_string();
+
+            if self.payment_credentials.contains(&payment) {
+                return true;
+            }
+
+            if let Some(stake) = shelley.delegation().as_hash() {
+                if self.stake_credentials.contains(&stake.to_string()) {
+                    return true;
+                }
+            }
+        }
+
+        utxo.non_ada_assets()
+            .iter()
+            .any(|x| self.policy_ids.contains(&x.policy().to_string()))
+    }
+
+    /// The set and member tracking an output. The member only depends on the
+    /// output itself, so spending it removes the same value producing it
+    /// added, whatever the txs witness.
     fn get_key_value(
         &self,
         utxo: &MultiEraOutput,
-        tx: &MultiEraTx,
         output_ref: &(Hash<32>, u64),
     ) -> Option<(String, String)> {
-        if let Some(address) = utxo.address().map(|addr| addr.to_string()).ok() {
-            if self.config.filter.iter().any(|addr| address.eq(addr)) {
-                let mut data = serde_json::Value::Object(serde_json::Map::new());
-                let address_as_key = self.config.address_as_key.unwrap_or(false);
-                let key: String;
-
-                if address_as_key {
-                    key = address;
-                    data["tx_hash"] = serde_json::Value::String(hex::encode(output_ref.0.to_vec()));
-                    data["output_index"] =
-                        serde_json::Value::from(serde_json::Number::from(output_ref.1));
-                } else {
-                    key = format!("{}#{}", hex::encode(output_ref.0.to_vec()), output_ref.1);
-                    data["address"] = serde_json::Value::String(address);
-                }
+        let address = utxo.address().ok()?;
 
-                if let Some(datum) = resolve_datum(utxo, tx) {
-                    data["datum"] = serde_json::Value::String(hex::encode(
-                        datum.encode_fragment().ok().unwrap(),
-                    ));
-                } else if let Some(PseudoDatumOption::Hash(h)) = utxo.datum() {
-                    data["datum_hash"] = serde_json::Value::String(hex::encode(h.to_vec()));
-                }
+        if !self.matches(utxo, &address) {
+            return None;
+        }
 
-                let mut assets: Vec<serde_json::Value> = vec![json!({
-                    "unit": "lovelace",
-                    "quantity": format!("{}", utxo.lovelace_amount())
-                })];
-
-                for asset in utxo.non_ada_assets() {
-                    match asset {
-                        MultiEraPolicyAssets::AlonzoCompatibleOutput(_, pairs) => assets.append(
-                            &mut pairs
-                                .iter()
-                                .map(|(name, amount)| {
-                                    json!({
-                                        "unit": name,
-                                        "quantity": format!("{}", amount)
-                                    })
-                                })
-                                .collect::<Vec<serde_json::Value>>(),
-                        ),
-                        _ => todo!(),
-                    }
+        let address = address.to_string();
+        let mut data = json!({});
+
+        let key = if self.config.address_as_key.unwrap_or(false) {
+            data["tx_hash"] = json!(output_ref.0.to_string());
+            data["output_index"] = json!(output_ref.1);
+            address
+        } else {
+            data["address"] = json!(address);
+            format!("{}#{}", output_ref.0, output_ref.1)
+        };
+
+        match utxo.datum() {
+            Some(PseudoDatumOption::Data(CborWrap(datum))) => {
+                data["datum"] = json!(hex::encode(datum.raw_cbor()));
+                data["datum_hash"] = json!(datum.original_hash().to_string());
+                data["inline_datum"] = json!(true);
+            }
+            Some(PseudoDatumOption::Hash(hash)) => {
+                data["datum_hash"] = json!(hash.to_string());
+                data["inline_datum"] = json!(false);
+            }
+            None => (),
+        }
+
+        if let Some(script) = utxo.script_ref() {
+            if let Ok(cbor) = minicbor::to_vec(&script) {
+                if let Some((kind, hash, _)) = decode_script_ref(&cbor) {
+                    data["reference_script"] = json!({ "type": kind, "hash": hash.to_string() });
                 }
+            }
+        }
+
+        let mut assets = vec![json!({
+            "unit": "lovelace",
+            "quantity": utxo.lovelace_amount().to_string(),
+        })];
 
-                data["amount"] = serde_json::Value::Array(assets);
-                return Some((key, data.to_string()));
+        for policy in utxo.non_ada_assets() {
+            for asset in policy.assets() {
+                let quantity = match asset.output_coin() {
+                    Some(x) => x,
+                    None => continue,
+                };
+
+                assets.push(json!({
+                    "unit": format!("{}{}", policy.policy(), hex::encode(asset.name())),
+                    "quantity": quantity.to_string(),
+                }));
             }
         }
 
-        None
+        data["amount"] = json!(assets);
+
+        Some((key, data.to_string()))
     }
 }
 
@@ -123,19 +236,31 @@ impl ReducerTrait for Reducer {
 
         for tx in block.txs().into_iter() {
             for consumed in tx.consumes().iter().map(|i| i.output_ref()) {
-                if let Some(utxo) = ctx.find_utxo(&consumed).ok() {
-                    if let Some((key, value)) =
-                        self.get_key_value(&utxo, &tx, &(consumed.hash().clone(), consumed.index()))
-                    {
-                        commands.push(CRDTCommand::set_remove(prefix, &key.as_str(), value));
+                if let Ok(utxo) = ctx.find_utxo(&consumed) {
+                    let output_ref = (*consumed.hash(), consumed.index());
+
+                    if let Some((key, value)) = self.get_key_value(&utxo, &output_ref) {
+                        commands.push(CRDTCommand::set_remove(prefix, &key, value));
                     }
                 }
             }
 
             for (index, produced) in tx.produces() {
-                let output_ref = (tx.hash().clone(), index as u64);
-                if let Some((key, value)) = self.get_key_value(&produced, &tx, &output_ref) {
-                    commands.push(CRDTCommand::set_add(None, &key, value));
+                let output_ref = (tx.hash(), index as u64);
+
+                if let Some((key, value)) = self.get_key_value(&produced, &output_ref) {
+                    commands.push(CRDTCommand::set_add(prefix, &key, value));
+
+                    // datums witnessed for tracked outputs are kept by hash,
+                    // scored by the number of outputs they were witnessed for
+                    if let Some((hash, cbor)) = witnessed_datum(&produced, &tx) {
+                        commands.push(CRDTCommand::sorted_set_add(
+                            prefix,
+                            &format!("datum.{}", hash),
+                            hex::encode(cbor),
+                            1,
+                        ));
+                    }
                 }
             }
         }
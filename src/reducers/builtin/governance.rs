@@ -0,0 +1,257 @@
+use pallas::codec::minicbor;
+use pallas::codec::utils::Nullable;
+use pallas::crypto::hash::Hash;
+use pallas::ledger::primitives::conway::{
+    GovAction, GovActionId, ProposalProcedure, Vote, Voter, VotingProcedure,
+};
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::certificates::{anchor_json, credential, nullable, reward_address};
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, _policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("governance".to_string()),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+}
+
+/// Governance actions are identified by the tx proposing them and the index
+/// of the proposal in it
+fn action_id(tx: &Hash<32>, index: u32) -> String {
+    format!("{}#{}", tx, index)
+}
+
+fn prev_action(x: &Nullable<GovActionId>) -> Option<String> {
+    nullable(x).map(|x| action_id(&x.transaction_id, x.action_index))
+}
+
+/// The type of a governance action and the json of its content
+fn action_json(action: &GovAction) -> (&'static str, serde_json::Value) {
+    match action {
+        GovAction::ParameterChange(prev, update, policy) => (
+            "parameter_change",
+            json!({
+                "prev_action": prev_action(prev),
+                "update": minicbor::to_vec(update.as_ref()).ok().map(hex::encode),
+                "policy_hash": nullable(policy).map(|x| x.to_string()),
+            }),
+        ),
+        GovAction::HardForkInitiation(prev, (major, minor)) => (
+            "hard_fork_initiation",
+            json!({
+                "prev_action": prev_action(prev),
+                "version": { "major": major, "minor": minor },
+            }),
+        ),
+        GovAction::TreasuryWithdrawals(withdrawals, policy) => (
+            "treasury_withdrawals",
+            json!({
+                "withdrawals": withdrawals
+                    .iter()
+                    .map(|(account, amount)| json!({
+                        "reward_account": reward_address(account),
+                        "amount": amount,
+                    }))
+                    .collect::<Vec<_>>(),
+                "policy_hash": nullable(policy).map(|x| x.to_string()),
+            }),
+        ),
+        GovAction::NoConfidence(prev) => {
+            ("no_confidence", json!({ "prev_action": prev_action(prev) }))
+        }
+        GovAction::UpdateCommittee(prev, remove, add, threshold) => (
+            "update_committee",
+            json!({
+                "prev_action": prev_action(prev),
+                "remove": remove.iter().map(credential).collect::<Vec<_>>(),
+                "add": add
+                    .iter()
+                    .map(|(x, epoch)| json!({
+                        "credential": credential(x),
+                        "expiration_epoch": epoch,
+                    }))
+                    .collect::<Vec<_>>(),
+                "threshold": {
+                    "numerator": threshold.numerator,
+                    "denominator": threshold.denominator,
+                },
+            }),
+        ),
+        GovAction::NewConstitution(prev, constitution) => (
+            "new_constitution",
+            json!({
+                "prev_action": prev_action(prev),
+                "anchor": anchor_json(&constitution.anchor),
+                "guardrail_script": nullable(&constitution.guardrail_script)
+                    .map(|x| x.to_string()),
+            }),
+        ),
+        GovAction::Information => ("information", json!({})),
+    }
+}
+
+/// The role of a voter followed by the hex hash of its key or script
+fn voter_id(x: &Voter) -> String {
+    match x {
+        Voter::ConstitutionalCommitteeKey(x) => format!("cc_key:{}", x),
+        Voter::ConstitutionalCommitteeScript(x) => format!("cc_script:{}", x),
+        Voter::DRepKey(x) => format!("drep_key:{}", x),
+        Voter::DRepScript(x) => format!("drep_script:{}", x),
+        Voter::StakePoolKey(x) => format!("spo:{}", x),
+    }
+}
+
+fn vote_name(x: &Vote) -> &'static str {
+    match x {
+        Vote::No => "no",
+        Vote::Yes => "yes",
+        Vote::Abstain => "abstain",
+    }
+}
+
+impl Reducer {
+    fn reduce_proposal(
+        &self,
+        id: String,
+        proposal: &ProposalProcedure,
+        tx: &str,
+        slot: u64,
+        commands: &mut Vec<CRDTCommand>,
+    ) {
+        let (kind, action) = action_json(&proposal.gov_action);
+
+        commands.push(CRDTCommand::sorted_set_add(
+            Some(&self.prefix),
+            "proposals",
+            id.clone(),
+            slot as i64,
+        ));
+
+        commands.push(CRDTCommand::sorted_set_add(
+            Some(&self.prefix),
+            &format!("proposals.{}", kind),
+            id.clone(),
+            slot as i64,
+        ));
+
+        if let GovAction::TreasuryWithdrawals(withdrawals, _) = &proposal.gov_action {
+            for (account, amount) in withdrawals.iter() {
+                commands.push(CRDTCommand::sorted_set_add(
+                    Some(&self.prefix),
+                    &format!("withdrawals.{}", reward_address(account)),
+                    id.clone(),
+                    *amount as i64,
+                ));
+            }
+        }
+
+        let procedure = json!({
+            "type": kind,
+            "action": action,
+            "deposit": proposal.deposit,
+            "reward_account": reward_address(&proposal.reward_account),
+            "anchor": anchor_json(&proposal.anchor),
+            "tx": tx,
+        });
+
+        commands.push(CRDTCommand::last_write_wins(
+            Some(&self.prefix),
+            &id,
+            procedure.to_string(),
+            slot,
+        ));
+    }
+
+    fn reduce_vote(
+        &self,
+        action: &GovActionId,
+        voter: &Voter,
+        procedure: &VotingProcedure,
+        tx: &str,
+        slot: u64,
+        commands: &mut Vec<CRDTCommand>,
+    ) {
+        let action = action_id(&action.transaction_id, action.action_index);
+        let voter = voter_id(voter);
+
+        // voters can change their vote, the set counts the votes each cast
+        commands.push(CRDTCommand::sorted_set_add(
+            Some(&self.prefix),
+            &format!("{}.votes", action),
+            voter.clone(),
+            1,
+        ));
+
+        let vote = json!({
+            "vote": vote_name(&procedure.vote),
+            "anchor": nullable(&procedure.anchor).as_ref().map(anchor_json),
+            "tx": tx,
+        });
+
+        commands.push(CRDTCommand::last_write_wins(
+            Some(&self.prefix),
+            &format!("{}.votes.{}", action, voter),
+            vote.to_string(),
+            slot,
+        ));
+    }
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        _ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let slot = block.slot();
+        let mut commands = vec![];
+
+        // proposals and votes of failed txs never take effect
+        for tx in block.txs().iter().filter(|tx| tx.is_valid()) {
+            let body = match tx.as_conway() {
+                Some(x) => &x.transaction_body,
+                None => continue,
+            };
+
+            let hash = tx.hash();
+            let tx_hash = hash.to_string();
+
+            if let Some(proposals) = &body.proposal_procedures {
+                for (index, proposal) in proposals.iter().enumerate() {
+                    let id = action_id(&hash, index as u32);
+                    self.reduce_proposal(id, proposal, &tx_hash, slot, &mut commands);
+                }
+            }
+
+            if let Some(votes) = &body.voting_procedures {
+                for (voter, procedures) in votes.iter() {
+                    for (action, procedure) in procedures.iter() {
+                        self.reduce_vote(action, voter, procedure, &tx_hash, slot, &mut commands);
+                    }
+                }
+            }
+        }
+
+        Ok(super::last_versions(commands))
+    }
+}
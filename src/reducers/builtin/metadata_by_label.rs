@@ -0,0 +1,79 @@
+use pallas::interop::utxorpc::Mapper;
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+
+use crate::framework::cip::metadatum_to_json;
+use crate::framework::model::{BlockContext, CRDTCommand};
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+    /// metadata labels to track, all of them when empty
+    #[serde(default)]
+    pub labels: Vec<u64>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, _policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("metadata_by_label".to_string()),
+            labels: self.labels,
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+    labels: Vec<u64>,
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        _ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let slot = block.slot();
+        let mapper = Mapper::new(BlockContext::default());
+        let mut commands = vec![];
+
+        for tx in block.txs() {
+            let hash = tx.hash().to_string();
+            let mut metadata = serde_json::Map::new();
+
+            for (label, metadatum) in tx.metadata().collect::<Vec<_>>() {
+                if !self.labels.is_empty() && !self.labels.contains(label) {
+                    continue;
+                }
+
+                commands.push(CRDTCommand::sorted_set_add(
+                    Some(&self.prefix),
+                    &label.to_string(),
+                    hash.clone(),
+                    slot as i64,
+                ));
+
+                let metadatum = mapper.map_metadatum(metadatum);
+                metadata.insert(label.to_string(), metadatum_to_json(&metadatum));
+            }
+
+            if !metadata.is_empty() {
+                commands.push(CRDTCommand::last_write_wins(
+                    Some(&self.prefix),
+                    &hash,
+                    serde_json::Value::Object(metadata),
+                    slot,
+                ));
+            }
+        }
+
+        Ok(commands)
+    }
+}
@@ -1,22 +1,67 @@
+use std::collections::HashSet;
+
 use gasket::framework::*;
+use pallas::ledger::traverse::wellknown::GenesisValues;
 use pallas::ledger::traverse::MultiEraBlock;
 use serde::Deserialize;
 
 use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
 use crate::framework::*;
 
+mod asset_holders;
+mod asset_registry;
+mod balance_by_address;
+mod balance_by_stake;
+mod certificates;
+mod datums_and_scripts;
+mod drep_registry;
+mod epoch_stats;
 mod full_utxos_by_address;
+mod governance;
+mod metadata_by_label;
+mod pool_registry;
+mod stake_delegation;
+mod tx_history_by_address;
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum ReducerConfig {
     FullUtxosByAddress(full_utxos_by_address::Config),
+    BalanceByAddress(balance_by_address::Config),
+    BalanceByStake(balance_by_stake::Config),
+    TxHistoryByAddress(tx_history_by_address::Config),
+    AssetRegistry(asset_registry::Config),
+    AssetHolders(asset_holders::Config),
+    StakeDelegation(stake_delegation::Config),
+    PoolRegistry(pool_registry::Config),
+    Governance(governance::Config),
+    DRepRegistry(drep_registry::Config),
+    DatumsAndScripts(datums_and_scripts::Config),
+    EpochStats(epoch_stats::Config),
+    MetadataByLabel(metadata_by_label::Config),
 }
 
 impl ReducerConfig {
-    pub fn into_reducer(self) -> Box<dyn ReducerTrait> {
+    pub fn into_reducer(
+        self,
+        policy: &RuntimePolicy,
+        chain: &GenesisValues,
+    ) -> Box<dyn ReducerTrait> {
         match self {
-            ReducerConfig::FullUtxosByAddress(x) => x.plugin(),
+            ReducerConfig::FullUtxosByAddress(x) => x.plugin(policy),
+            ReducerConfig::BalanceByAddress(x) => x.plugin(policy),
+            ReducerConfig::BalanceByStake(x) => x.plugin(policy),
+            ReducerConfig::TxHistoryByAddress(x) => x.plugin(policy),
+            ReducerConfig::AssetRegistry(x) => x.plugin(policy),
+            ReducerConfig::AssetHolders(x) => x.plugin(policy),
+            ReducerConfig::StakeDelegation(x) => x.plugin(policy),
+            ReducerConfig::PoolRegistry(x) => x.plugin(policy),
+            ReducerConfig::Governance(x) => x.plugin(policy),
+            ReducerConfig::DRepRegistry(x) => x.plugin(policy),
+            ReducerConfig::DatumsAndScripts(x) => x.plugin(policy),
+            ReducerConfig::EpochStats(x) => x.plugin(policy, chain),
+            ReducerConfig::MetadataByLabel(x) => x.plugin(policy),
         }
     }
 }
@@ -24,15 +69,21 @@ impl ReducerConfig {
 #[derive(Deserialize)]
 pub struct Config {
     reducers: Vec<ReducerConfig>,
+    /// what to do when a reducer can't find the data it needs, like the
+    /// outputs consumed by a tx
+    #[serde(default)]
+    policy: RuntimePolicy,
 }
 
 impl Config {
-    pub fn bootstrapper(self, _ctx: &Context) -> Result<Stage, Error> {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let chain: GenesisValues = ctx.chain.clone().try_into()?;
+
         let stage = Stage {
             reducers: self
                 .reducers
                 .into_iter()
-                .map(|x| x.into_reducer())
+                .map(|x| x.into_reducer(&self.policy, &chain))
                 .collect(),
             ..Default::default()
         };
@@ -63,31 +114,47 @@ impl From<&Stage> for Worker {
 }
 
 gasket::impl_splitter!(|_worker: Worker, stage: Stage, unit: ChainEvent| => {
-    let record = unit.record();
-    if record.is_none() {
-        return Ok(());
-    }
+    let (point, record, rollback) = match unit {
+        ChainEvent::Apply(point, record) => (point, record, false),
+        ChainEvent::Undo(point, record) => (point, record, true),
+        // builtin reducers don't track the mempool, nothing to do on resets
+        _ => return Ok(()),
+    };
+
+    // blocks that aren't enriched only resolve the outputs they produce
+    let (cbor, mut ctx) = match record {
+        Record::EnrichedBlockPayload(block, ctx) => (block, ctx.clone()),
+        Record::RawBlockPayload(block) => (block, model::BlockContext::default()),
+        _ => {
+            return Err(Error::reducer(
+                "builtin reducers need cbor blocks, not utxorpc ones",
+            ))
+            .or_panic();
+        }
+    };
 
-    let record = record.unwrap();
+    let block = MultiEraBlock::decode(cbor)
+        .map_err(Error::cbor)
+        .or_panic()?;
 
-    let commands = match record {
-        Record::EnrichedBlockPayload(block, ctx) => {
-            let block = MultiEraBlock::decode(block)
-            .map_err(Error::cbor)
-            .or_panic()?;
+    // outputs spent within the block they're produced in are always known
+    ctx.import_block_outputs(&block);
 
-            let mut commands: Vec<CRDTCommand> = Vec::new();
+    let mut commands: Vec<CRDTCommand> = Vec::new();
 
-            for x in stage.reducers.iter_mut() {
-                commands.append(&mut x.reduce_block(&block, ctx).await.or_retry()?)
-            }
+    for x in stage.reducers.iter_mut() {
+        let mut reduced = match rollback {
+            false => x.reduce_block(&block, &ctx).await.or_retry()?,
+            true => x.undo_block(&block, &ctx).await.or_retry()?,
+        };
 
-            Ok(commands)
-        },
-        _ => todo!(),
-    }?;
+        commands.append(&mut reduced)
+    }
 
-    Some(ChainEvent::apply(unit.point().clone(), Record::CRDTCommand(commands)))
+    match rollback {
+        false => Some(ChainEvent::apply(point.clone(), Record::CRDTCommand(commands))),
+        true => Some(ChainEvent::undo(point.clone(), Record::CRDTCommand(commands))),
+    }
 });
 
 #[async_trait::async_trait]
@@ -97,8 +164,45 @@ pub trait ReducerTrait: Send + Sync {
         block: &'b MultiEraBlock<'b>,
         ctx: &model::BlockContext,
     ) -> Result<Vec<CRDTCommand>, Error>;
+
+    /// Commands reverting the block when it's rolled back. By default these
+    /// are the commands of `reduce_block` inverted, in reverse order; the
+    /// ones that can't be inverted are dropped, so reducers relying on them
+    /// have to override this.
+    async fn undo_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let commands = self.reduce_block(block, ctx).await?;
+
+        Ok(commands
+            .into_iter()
+            .rev()
+            .filter_map(CRDTCommand::invert)
+            .collect())
+    }
+}
+
+/// Keeps only the last `LastWriteWins` of each key. Versions written within
+/// a block all get its slot as score, so the sorted set couldn't tell which
+/// of them came last.
+fn last_versions(commands: Vec<CRDTCommand>) -> Vec<CRDTCommand> {
+    let mut written = HashSet::new();
+
+    let mut commands: Vec<_> = commands
+        .into_iter()
+        .rev()
+        .filter(|x| match x {
+            CRDTCommand::LastWriteWins(key, _, _) => written.insert(key.clone()),
+            _ => true,
+        })
+        .collect();
+
+    commands.reverse();
+    commands
 }
 
 trait ReducerConfigTrait {
-    fn plugin(self) -> Box<dyn ReducerTrait>;
+    fn plugin(self, policy: &RuntimePolicy) -> Box<dyn ReducerTrait>;
 }
@@ -0,0 +1,88 @@
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::certificates::{tx_events, CertEvent};
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, _policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("pool_registry".to_string()),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        _ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let slot = block.slot();
+        let mut commands = vec![];
+
+        for tx in block.txs() {
+            let hash = tx.hash().to_string();
+
+            for event in tx_events(&tx) {
+                match event {
+                    CertEvent::PoolRegistration(pool, mut params) => {
+                        params["tx"] = json!(hash);
+
+                        // counts the registrations of the pool, so a rollback
+                        // only drops it from the index along with the first one
+                        commands.push(CRDTCommand::sorted_set_add(
+                            None,
+                            &self.prefix,
+                            pool.clone(),
+                            1,
+                        ));
+
+                        commands.push(CRDTCommand::last_write_wins(
+                            Some(&self.prefix),
+                            &format!("{}.params", pool),
+                            params.to_string(),
+                            slot,
+                        ));
+
+                        // registering again cancels a pending retirement
+                        commands.push(CRDTCommand::last_write_wins(
+                            Some(&self.prefix),
+                            &format!("{}.retirement", pool),
+                            json!({ "epoch": null, "tx": hash }).to_string(),
+                            slot,
+                        ));
+                    }
+                    CertEvent::PoolRetirement(pool, epoch) => {
+                        commands.push(CRDTCommand::last_write_wins(
+                            Some(&self.prefix),
+                            &format!("{}.retirement", pool),
+                            json!({ "epoch": epoch, "tx": hash }).to_string(),
+                            slot,
+                        ));
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(super::last_versions(commands))
+    }
+}
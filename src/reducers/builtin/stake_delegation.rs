@@ -0,0 +1,91 @@
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::certificates::{tx_events, CertEvent};
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, _policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("stake_delegation".to_string()),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+}
+
+impl Reducer {
+    /// a new version of `<prefix>.<credential>.<field>`, the tx hash keeps
+    /// versions written by different txs apart
+    fn version(
+        &self,
+        credential: &str,
+        field: &str,
+        value: serde_json::Value,
+        tx: &str,
+        slot: u64,
+    ) -> CRDTCommand {
+        let mut version = json!({ "tx": tx });
+        version[field] = value;
+
+        CRDTCommand::last_write_wins(
+            Some(&self.prefix),
+            &format!("{}.{}", credential, field),
+            version.to_string(),
+            slot,
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        _ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let slot = block.slot();
+        let mut commands = vec![];
+
+        for tx in block.txs() {
+            let hash = tx.hash().to_string();
+
+            for event in tx_events(&tx) {
+                match event {
+                    CertEvent::Registration(x) => {
+                        commands.push(self.version(&x, "registered", json!(true), &hash, slot));
+                    }
+                    // deregistering drops both delegations
+                    CertEvent::Deregistration(x) => {
+                        commands.push(self.version(&x, "registered", json!(false), &hash, slot));
+                        commands.push(self.version(&x, "pool", json!(null), &hash, slot));
+                        commands.push(self.version(&x, "drep", json!(null), &hash, slot));
+                    }
+                    CertEvent::PoolDelegation(x, pool) => {
+                        commands.push(self.version(&x, "pool", json!(pool), &hash, slot));
+                    }
+                    CertEvent::DRepDelegation(x, drep) => {
+                        commands.push(self.version(&x, "drep", drep, &hash, slot));
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(super::last_versions(commands))
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeSet;
+
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::policies::RuntimePolicy;
+use crate::framework::{model, Error};
+
+use super::{ReducerConfigTrait, ReducerTrait};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub prefix: Option<String>,
+    /// addresses to track, all of them when empty
+    #[serde(default)]
+    pub filter: Vec<String>,
+}
+
+impl ReducerConfigTrait for Config {
+    fn plugin(self, policy: &RuntimePolicy) -> Box<dyn ReducerTrait> {
+        let reducer = Reducer {
+            prefix: self.prefix.unwrap_or("tx_history_by_address".to_string()),
+            filter: self.filter,
+            policy: policy.clone(),
+        };
+
+        Box::new(reducer)
+    }
+}
+
+pub struct Reducer {
+    prefix: String,
+    filter: Vec<String>,
+    policy: RuntimePolicy,
+}
+
+#[async_trait::async_trait]
+impl ReducerTrait for Reducer {
+    async fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+    ) -> Result<Vec<CRDTCommand>, Error> {
+        let slot = block.slot() as i64;
+        let mut commands = vec![];
+
+        for tx in block.txs() {
+            let consumed = ctx
+                .find_consumed_txos(&tx, &self.policy)?
+                .into_iter()
+                .map(|(_, output)| output);
+
+            // an address both spending and receiving in the same tx gets a
+            // single entry, its score is the slot of the block
+            let addresses: BTreeSet<_> = consumed
+                .chain(tx.produces().into_iter().map(|(_, output)| output))
+                .filter_map(|output| output.address().ok())
+                .map(|address| address.to_string())
+                .filter(|address| self.filter.is_empty() || self.filter.contains(address))
+                .collect();
+
+            let hash = tx.hash().to_string();
+
+            for address in addresses {
+                commands.push(CRDTCommand::sorted_set_add(
+                    Some(&self.prefix),
+                    &address,
+                    hash.clone(),
+                    slot,
+                ));
+            }
+        }
+
+        Ok(commands)
+    }
+}
@@ -0,0 +1,120 @@
+use deno_runtime::deno_core::anyhow::anyhow;
+use deno_runtime::deno_core::error::AnyError;
+use deno_runtime::deno_core::op2;
+use pallas::crypto::hash::Hasher;
+use pallas::ledger::addresses::{Address, ShelleyDelegationPart, ShelleyPaymentPart, StakePayload};
+use pallas::ledger::primitives::babbage::PlutusData;
+use pallas::ledger::primitives::{Fragment, ToCanonicalJson};
+use serde_json::json;
+use utxorpc_spec::utxorpc::v1alpha::cardano as u5c;
+
+use crate::framework::cip::{cip67_label, parse_cip25, parse_cip68};
+
+fn address_to_json(address: &Address) -> Result<serde_json::Value, AnyError> {
+    let value = match address {
+        Address::Byron(x) => json!({
+            "kind": "byron",
+            "address": x.to_base58(),
+        }),
+        Address::Shelley(x) => {
+            let payment = match x.payment() {
+                ShelleyPaymentPart::Key(h) => json!({ "type": "key", "hash": h.to_string() }),
+                ShelleyPaymentPart::Script(h) => json!({ "type": "script", "hash": h.to_string() }),
+            };
+
+            let delegation = match x.delegation() {
+                ShelleyDelegationPart::Key(h) => json!({ "type": "key", "hash": h.to_string() }),
+                ShelleyDelegationPart::Script(h) => {
+                    json!({ "type": "script", "hash": h.to_string() })
+                }
+                ShelleyDelegationPart::Pointer(p) => json!({
+                    "type": "pointer",
+                    "slot": p.slot(),
+                    "tx_idx": p.tx_idx(),
+                    "cert_idx": p.cert_idx(),
+                }),
+                ShelleyDelegationPart::Null => serde_json::Value::Null,
+            };
+
+            json!({
+                "kind": "shelley",
+                "address": x.to_bech32()?,
+                "network": u8::from(x.network()),
+                "payment": payment,
+                "delegation": delegation,
+            })
+        }
+        Address::Stake(x) => {
+            let credential = match x.payload() {
+                StakePayload::Stake(h) => json!({ "type": "key", "hash": h.to_string() }),
+                StakePayload::Script(h) => json!({ "type": "script", "hash": h.to_string() }),
+            };
+
+            json!({
+                "kind": "stake",
+                "address": x.to_bech32()?,
+                "network": u8::from(x.network()),
+                "delegation": credential,
+            })
+        }
+    };
+
+    Ok(value)
+}
+
+#[op2]
+#[serde]
+pub fn op_address_from_bytes(#[buffer] bytes: &[u8]) -> Result<serde_json::Value, AnyError> {
+    let address = Address::from_bytes(bytes).map_err(|e| anyhow!("invalid address: {}", e))?;
+    address_to_json(&address)
+}
+
+#[op2]
+#[serde]
+pub fn op_address_from_bech32(#[string] bech32: String) -> Result<serde_json::Value, AnyError> {
+    let address = Address::from_bech32(&bech32).map_err(|e| anyhow!("invalid address: {}", e))?;
+    address_to_json(&address)
+}
+
+#[op2]
+#[serde]
+pub fn op_plutus_data_to_json(#[buffer] cbor: &[u8]) -> Result<serde_json::Value, AnyError> {
+    let data =
+        PlutusData::decode_fragment(cbor).map_err(|e| anyhow!("invalid plutus data: {}", e))?;
+    Ok(data.to_json())
+}
+
+#[op2]
+#[string]
+pub fn op_blake2b_224(#[buffer] bytes: &[u8]) -> String {
+    Hasher::<224>::hash(bytes).to_string()
+}
+
+#[op2]
+#[string]
+pub fn op_blake2b_256(#[buffer] bytes: &[u8]) -> String {
+    Hasher::<256>::hash(bytes).to_string()
+}
+
+#[op2]
+#[serde]
+pub fn op_cip25_parse(
+    #[serde] metadatum: serde_json::Value,
+) -> Result<serde_json::Value, AnyError> {
+    let metadatum: u5c::Metadatum = serde_json::from_value(metadatum)?;
+    parse_cip25(&metadatum).map_err(|e| anyhow!("{}", e))
+}
+
+#[op2]
+#[serde]
+pub fn op_cip68_parse(#[buffer] cbor: &[u8]) -> Result<serde_json::Value, AnyError> {
+    let data =
+        PlutusData::decode_fragment(cbor).map_err(|e| anyhow!("invalid plutus data: {}", e))?;
+    parse_cip68(&data).map_err(|e| anyhow!("{}", e))
+}
+
+#[op2]
+#[serde]
+pub fn op_cip67_label(#[buffer] asset_name: &[u8]) -> Option<u16> {
+    cip67_label(asset_name)
+}
@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use deno_ast::{MediaType, ParseParams, SourceTextInfo};
+use deno_runtime::deno_core;
+use deno_runtime::deno_core::anyhow::{anyhow, bail};
+use deno_runtime::deno_core::error::AnyError;
+use deno_runtime::deno_core::{
+    ModuleLoader, ModuleSource, ModuleSourceFuture, ModuleSpecifier, ModuleType, ResolutionKind,
+};
+use futures::FutureExt;
+use import_map::ImportMap;
+
+use crate::framework::errors::Error;
+
+/// Parses the import map json at `path`, relative specifiers in it are
+/// resolved against the directory of the file.
+pub fn load_import_map(path: &Path) -> Result<ImportMap, Error> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        Error::config(format!("invalid import map {}: {}", path.display(), e))
+    };
+
+    let path = std::fs::canonicalize(path).map_err(|e| invalid(&e))?;
+    let json = std::fs::read_to_string(&path).map_err(|e| invalid(&e))?;
+
+    let base =
+        ModuleSpecifier::from_file_path(&path).map_err(|_| invalid(&"not an absolute path"))?;
+
+    let parsed = import_map::parse_from_json(&base, &json).map_err(|e| invalid(&e))?;
+
+    for diagnostic in parsed.diagnostics {
+        tracing::warn!("import map {}: {}", path.display(), diagnostic);
+    }
+
+    Ok(parsed.import_map)
+}
+
+/// Loads reducer modules from disk, resolving specifiers through the
+/// optional import map and transpiling typescript on the way in.
+pub struct Loader {
+    import_map: Option<ImportMap>,
+    loaded: RefCell<Vec<PathBuf>>,
+}
+
+impl Loader {
+    pub fn new(import_map: Option<ImportMap>) -> Self {
+        Self {
+            import_map,
+            loaded: Default::default(),
+        }
+    }
+
+    /// files of every module loaded so far
+    pub fn loaded(&self) -> Vec<PathBuf> {
+        self.loaded.borrow().clone()
+    }
+}
+
+fn to_path(specifier: &ModuleSpecifier) -> Result<PathBuf, AnyError> {
+    specifier
+        .to_file_path()
+        .map_err(|_| anyhow!("only local modules can be imported, got {}", specifier))
+}
+
+fn load_source(specifier: &ModuleSpecifier) -> Result<ModuleSource, AnyError> {
+    let path = to_path(specifier)?;
+
+    let media_type = MediaType::from_path(&path);
+
+    let (module_type, transpile) = match media_type {
+        MediaType::JavaScript | MediaType::Mjs | MediaType::Cjs => (ModuleType::JavaScript, false),
+        MediaType::Jsx
+        | MediaType::TypeScript
+        | MediaType::Mts
+        | MediaType::Cts
+        | MediaType::Dts
+        | MediaType::Dmts
+        | MediaType::Dcts
+        | MediaType::Tsx => (ModuleType::JavaScript, true),
+        MediaType::Json => (ModuleType::Json, false),
+        _ => bail!("unsupported module type {}", path.display()),
+    };
+
+    let code = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("can't read module {}: {}", path.display(), e))?;
+
+    // types are stripped, not checked
+    let code = if transpile {
+        let parsed = deno_ast::parse_module(ParseParams {
+            specifier: specifier.to_string(),
+            text_info: SourceTextInfo::from_string(code),
+            media_type,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        })?;
+
+        let emitted = parsed.transpile(&deno_ast::EmitOptions {
+            inline_source_map: true,
+            inline_sources: true,
+            ..Default::default()
+        })?;
+
+        emitted.text
+    } else {
+        code
+    };
+
+    Ok(ModuleSource::new(module_type, code.into(), specifier))
+}
+
+impl ModuleLoader for Loader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, AnyError> {
+        if let (Some(map), Ok(referrer)) = (&self.import_map, ModuleSpecifier::parse(referrer)) {
+            if let Ok(resolved) = map.resolve(specifier, &referrer) {
+                return Ok(resolved);
+            }
+        }
+
+        Ok(deno_core::resolve_import(specifier, referrer)?)
+    }
+
+    fn load(
+        &self,
+        specifier: &ModuleSpecifier,
+        _maybe_referrer: Option<&ModuleSpecifier>,
+        _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+        if let Ok(path) = to_path(specifier) {
+            self.loaded.borrow_mut().push(path);
+        }
+
+        futures::future::ready(load_source(specifier)).boxed_local()
+    }
+}
@@ -0,0 +1,1136 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant, SystemTime};
+
+use deno_runtime::deno_core;
+use deno_runtime::deno_core::op2;
+use deno_runtime::deno_core::v8;
+use deno_runtime::deno_core::ModuleSpecifier;
+use deno_runtime::deno_core::OpState;
+use deno_runtime::permissions::{Permissions, PermissionsContainer, PermissionsOptions};
+use deno_runtime::worker::MainWorker as DenoWorker;
+use deno_runtime::worker::WorkerOptions;
+use gasket::framework::*;
+use import_map::ImportMap;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::framework::model::CRDTCommand;
+use crate::framework::*;
+
+mod cardano;
+mod loader;
+mod state;
+
+use cardano::{
+    op_address_from_bech32, op_address_from_bytes, op_blake2b_224, op_blake2b_256, op_cip25_parse,
+    op_cip67_label, op_cip68_parse, op_plutus_data_to_json,
+};
+use state::{op_state_delete, op_state_get, op_state_set, State, MAX_JOURNAL_BLOCKS};
+
+const SYNC_CALL_SNIPPET: &str = r#"(module, method) => { Deno[Deno.internal].core.ops.op_put_record(globalThis.__reducers[module][method](Deno[Deno.internal].core.ops.op_pop_record(), Deno[Deno.internal].core.ops.op_pop_context())); }"#;
+const ASYNC_CALL_SNIPPET: &str = r#"(module, method) => { Promise.resolve(globalThis.__reducers[module][method](Deno[Deno.internal].core.ops.op_pop_record(), Deno[Deno.internal].core.ops.op_pop_context())).then(x => Deno[Deno.internal].core.ops.op_put_record(x)); }"#;
+
+deno_core::extension!(
+    deno_reducer,
+    ops = [
+        op_pop_record,
+        op_pop_context,
+        op_put_record,
+        op_state_get,
+        op_state_set,
+        op_state_delete,
+        op_address_from_bytes,
+        op_address_from_bech32,
+        op_plutus_data_to_json,
+        op_blake2b_224,
+        op_blake2b_256,
+        op_cip25_parse,
+        op_cip68_parse,
+        op_cip67_label
+    ]
+);
+
+/// exposes the lyra ops to reducer code under a `lyra` global
+const PRELUDE: &str = r#"
+{
+  const ops = Deno[Deno.internal].core.ops;
+
+  // UTxO RPC json encodes bytes as base64
+  const toBytes = (x) => {
+    if (x instanceof Uint8Array) return x;
+    if (typeof x === "string") return Uint8Array.from(atob(x), (c) => c.charCodeAt(0));
+    throw new TypeError("expected a Uint8Array or a base64 string");
+  };
+
+  globalThis.lyra = {
+    state: {
+      get: (key) => ops.op_state_get(key),
+      set: (key, value) => ops.op_state_set(key, value),
+      delete: (key) => ops.op_state_delete(key),
+    },
+    bytes: {
+      fromHex: (hex) => Uint8Array.from(hex.match(/../g) ?? [], (x) => parseInt(x, 16)),
+      toHex: (bytes) => Array.from(toBytes(bytes), (x) => x.toString(16).padStart(2, "0")).join(""),
+    },
+    address: {
+      fromBytes: (bytes) => ops.op_address_from_bytes(toBytes(bytes)),
+      fromBech32: (bech32) => ops.op_address_from_bech32(bech32),
+    },
+    plutus: {
+      toJson: (cbor) => ops.op_plutus_data_to_json(toBytes(cbor)),
+    },
+    hash: {
+      blake2b224: (bytes) => ops.op_blake2b_224(toBytes(bytes)),
+      blake2b256: (bytes) => ops.op_blake2b_256(toBytes(bytes)),
+    },
+    metadata: {
+      cip25: (metadatum) => ops.op_cip25_parse(metadatum),
+      cip68: (cbor) => ops.op_cip68_parse(toBytes(cbor)),
+      cip67Label: (assetName) => ops.op_cip67_label(toBytes(assetName)),
+    },
+  };
+}
+"#;
+
+struct ReducerInput(serde_json::Value);
+
+struct ReducerContext(serde_json::Value);
+
+#[op2]
+#[serde]
+pub fn op_pop_record(state: &mut OpState) -> Result<serde_json::Value, deno_core::error::AnyError> {
+    let ReducerInput(input) = state.take();
+    Ok(input)
+}
+
+#[op2]
+#[serde]
+pub fn op_pop_context(
+    state: &mut OpState,
+) -> Result<serde_json::Value, deno_core::error::AnyError> {
+    let ReducerContext(context) = state.take();
+    Ok(context)
+}
+
+#[op2]
+pub fn op_put_record(
+    state: &mut OpState,
+    #[serde] value: serde_json::Value,
+) -> Result<(), deno_core::error::AnyError> {
+    match value {
+        serde_json::Value::Null => (),
+        _ => state.put(value),
+    };
+
+    Ok(())
+}
+
+/// What the reducer script is allowed to touch. Follows deno's own
+/// `--allow-*` flags: a missing key denies everything of that kind, an empty
+/// list allows all of it.
+#[derive(Deserialize, Default, Clone)]
+pub struct PermissionsConfig {
+    allow_read: Option<Vec<PathBuf>>,
+    allow_write: Option<Vec<PathBuf>>,
+    allow_net: Option<Vec<String>>,
+    allow_env: Option<Vec<String>>,
+    allow_run: Option<Vec<String>>,
+    allow_sys: Option<Vec<String>>,
+    #[serde(default)]
+    allow_hrtime: bool,
+}
+
+impl PermissionsConfig {
+    fn container(&self) -> Result<PermissionsContainer, Error> {
+        let options = PermissionsOptions {
+            allow_read: self.allow_read.clone(),
+            allow_write: self.allow_write.clone(),
+            allow_net: self.allow_net.clone(),
+            allow_env: self.allow_env.clone(),
+            allow_run: self.allow_run.clone(),
+            allow_sys: self.allow_sys.clone(),
+            allow_hrtime: self.allow_hrtime,
+            prompt: false,
+            ..Default::default()
+        };
+
+        Permissions::from_options(&options)
+            .map(PermissionsContainer::new)
+            .map_err(|e| Error::config(format!("invalid deno permissions: {}", e)))
+    }
+}
+
+/// Development option that reloads the reducer whenever one of its modules
+/// changes on disk.
+#[derive(Deserialize, Clone)]
+pub struct HotReloadConfig {
+    poll_interval_ms: Option<u64>,
+    /// restarts the pipeline from this point after reloading, so that blocks
+    /// are indexed again with the new reducer
+    rewind: Option<IntersectConfig>,
+}
+
+impl HotReloadConfig {
+    fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms.unwrap_or(1000))
+    }
+
+    /// first slot a rewind takes the storage back from
+    fn rewind_slot(&self) -> Option<u64> {
+        match self.rewind.as_ref()? {
+            IntersectConfig::Origin => Some(0),
+            IntersectConfig::Point(slot, _) => Some(slot + 1),
+            _ => None,
+        }
+    }
+}
+
+/// One of several reducer modules running side by side in the same stage.
+#[derive(Deserialize, Clone)]
+pub struct ModuleConfig {
+    reducer_module: String,
+    /// namespaces the storage keys and the `lyra.state` keys of the module
+    prefix: Option<String>,
+    /// postgres schema the sql of the module runs against
+    schema: Option<String>,
+}
+
+struct Module {
+    specifier: ModuleSpecifier,
+    prefix: Option<String>,
+    schema: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    reducer_module: Option<String>,
+    #[serde(default)]
+    modules: Vec<ModuleConfig>,
+    /// json import map used to resolve bare specifiers in the reducer module
+    import_map: Option<String>,
+    use_async: bool,
+    #[serde(default)]
+    permissions: PermissionsConfig,
+    /// max wall-clock time a single apply / undo / mempool call may take
+    timeout_ms: Option<u64>,
+    /// max size of the v8 heap, in megabytes
+    max_heap_mb: Option<usize>,
+    /// directory of the embedded store backing `lyra.state`
+    state_path: Option<String>,
+    /// max number of queued blocks handed to `applyBatch` in a single call
+    batch_size: Option<usize>,
+    hot_reload: Option<HotReloadConfig>,
+}
+
+impl Config {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        // fail on a bad permissions section now rather than when the worker starts
+        self.permissions.container()?;
+
+        let configs: Vec<_> = self
+            .reducer_module
+            .map(|reducer_module| ModuleConfig {
+                reducer_module,
+                prefix: None,
+                schema: None,
+            })
+            .into_iter()
+            .chain(self.modules)
+            .collect();
+
+        if configs.is_empty() {
+            return Err(Error::config("deno reducer requires at least one module"));
+        }
+
+        let mut prefixes: Vec<_> = configs.iter().map(|x| x.prefix.as_deref()).collect();
+        prefixes.sort();
+        prefixes.dedup();
+
+        if prefixes.len() != configs.len() {
+            return Err(Error::config(
+                "deno reducer modules must have distinct prefixes",
+            ));
+        }
+
+        // state writes of a batch can't be told apart block by block, so a
+        // rollback to a block in the middle of it couldn't be journaled
+        if self.batch_size.is_some_and(|x| x > 1) && self.state_path.is_some() {
+            return Err(Error::config(
+                "deno reducer batch_size can't be combined with state_path",
+            ));
+        }
+
+        let modules = configs
+            .into_iter()
+            .map(|x| {
+                let specifier = std::fs::canonicalize(&x.reducer_module)
+                    .ok()
+                    .and_then(|path| ModuleSpecifier::from_file_path(path).ok())
+                    .ok_or_else(|| {
+                        Error::config(format!("reducer module not found: {}", x.reducer_module))
+                    })?;
+
+                Ok(Module {
+                    specifier,
+                    prefix: x.prefix,
+                    schema: x.schema,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if let Some(rewind) = self.hot_reload.as_ref().and_then(|x| x.rewind.as_ref()) {
+            if !matches!(rewind, IntersectConfig::Origin | IntersectConfig::Point(..)) {
+                return Err(Error::config(
+                    "deno reducer hot_reload.rewind must be Origin or a Point",
+                ));
+            }
+
+            // rewinding undoes what was written after the point, which sql
+            // statements give no way to do
+            if ctx.storage_type == "Postgres" {
+                return Err(Error::config(
+                    "deno reducer hot_reload.rewind can't be used with postgres storage",
+                ));
+            }
+        }
+
+        // every block from here on is in the history once sent to storage
+        let history_start = match ctx.cursor.latest_known_point() {
+            Some(point) => Some(point.slot_or_default() + 1),
+            None => match &ctx.intersect {
+                IntersectConfig::Origin => Some(0),
+                IntersectConfig::Point(slot, _) => Some(slot + 1),
+                _ => None,
+            },
+        };
+
+        let import_map = self
+            .import_map
+            .map(|path| loader::load_import_map(&PathBuf::from(path)))
+            .transpose()?;
+
+        let stage = Stage {
+            modules,
+            import_map,
+            permissions: self.permissions,
+            timeout: self.timeout_ms.map(Duration::from_millis),
+            max_heap_mb: self.max_heap_mb,
+            state_path: self.state_path.map(PathBuf::from),
+            batch_size: self.batch_size.filter(|x| *x > 1),
+            hot_reload: self.hot_reload,
+            restart: ctx.restart.clone(),
+            storage_type: ctx.storage_type.clone(),
+            chain: ctx.chain.clone().try_into()?,
+            pending: None,
+            undo: ctx.undo.iter().cloned().collect(),
+            history: Default::default(),
+            history_start,
+            call_snippet: if self.use_async {
+                ASYNC_CALL_SNIPPET
+            } else {
+                SYNC_CALL_SNIPPET
+            },
+            input: Default::default(),
+            output: Default::default(),
+            ops_count: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
+
+/// Terminates the isolate once a reducer call outlives its deadline. It lives
+/// on its own thread because a busy JS loop never yields back to tokio.
+struct Watchdog {
+    deadlines: mpsc::Sender<Option<Instant>>,
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn spawn(isolate: v8::IsolateHandle) -> Self {
+        let (deadlines, rx) = mpsc::channel::<Option<Instant>>();
+        let fired = Arc::new(AtomicBool::new(false));
+        let flag = fired.clone();
+
+        std::thread::spawn(move || {
+            let mut deadline = None;
+
+            loop {
+                let next = match deadline {
+                    Some(x) => rx.recv_timeout(x.saturating_duration_since(Instant::now())),
+                    None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                };
+
+                match next {
+                    Ok(x) => deadline = x,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        flag.store(true, Ordering::SeqCst);
+                        isolate.terminate_execution();
+                        deadline = None;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        Self { deadlines, fired }
+    }
+
+    fn arm(&self, deadline: Instant) {
+        let _ = self.deadlines.send(Some(deadline));
+    }
+
+    /// returns true if the deadline was hit since the watchdog was armed
+    fn disarm(&self) -> bool {
+        let _ = self.deadlines.send(None);
+        self.fired.swap(false, Ordering::SeqCst)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// Boots the runtime and loads the reducer modules into it, returning the
+/// files of every module that got loaded along the way.
+async fn setup_deno(stage: &Stage) -> Result<(DenoWorker, Vec<PathBuf>), WorkerError> {
+    let main_module = deno_core::ModuleSpecifier::parse("data:text/javascript;base64,").unwrap();
+
+    let create_params = stage
+        .max_heap_mb
+        .map(|mb| v8::CreateParams::default().heap_limits(0, mb * 1024 * 1024));
+
+    let loader = Rc::new(loader::Loader::new(stage.import_map.clone()));
+
+    let mut deno: DenoWorker = DenoWorker::bootstrap_from_options(
+        main_module,
+        stage.permissions.container().or_panic()?,
+        WorkerOptions {
+            extensions: vec![deno_reducer::init_ops()],
+            module_loader: loader.clone(),
+            create_params,
+            ..Default::default()
+        },
+    );
+
+    if let Some(path) = &stage.state_path {
+        let state = State::open(path).or_panic()?;
+        deno.js_runtime.op_state().borrow_mut().put(state);
+    }
+
+    deno.execute_script(
+        "[lyra:prelude.js]",
+        deno_core::FastString::from_static(PRELUDE),
+    )
+    .map_err(Error::reducer)
+    .or_panic()?;
+
+    deno.execute_script(
+        "[lyra:reducers.js]",
+        deno_core::FastString::from_static("globalThis.__reducers = [];"),
+    )
+    .map_err(Error::reducer)
+    .or_panic()?;
+
+    for (index, module) in stage.modules.iter().enumerate() {
+        // compiles the whole module graph, syntax errors come back with their
+        // file and line
+        deno.js_runtime
+            .load_side_module(&module.specifier, None)
+            .await
+            .map_err(|e| Error::reducer(format!("can't load reducer module: {}", e)))
+            .or_panic()?;
+
+        let runtime_code = deno_core::FastString::from(format!(
+            r#"
+            import("{}").then(({{ apply, applyBatch, undo, mempool }}) => {{
+              globalThis.__reducers[{}] = {{
+                apply,
+                applyBatch,
+                undo,
+                mempool: mempool ?? (() => null),
+              }};
+            }});
+            "#,
+            module.specifier, index
+        ));
+
+        deno.execute_script("[lyra:runtime.js]", runtime_code)
+            .map_err(Error::reducer)
+            .or_panic()?;
+    }
+
+    // evaluates the module, top-level errors surface here
+    deno.run_event_loop(false)
+        .await
+        .map_err(|e| Error::reducer(format!("can't evaluate reducer module: {}", e)))
+        .or_panic()?;
+
+    if stage.batch_size.is_some() {
+        let check = deno_core::FastString::from_static(
+            r#"
+            if (globalThis.__reducers.some((x) => typeof x.applyBatch !== "function")) {
+              throw new Error("batch_size is set but a reducer module doesn't export applyBatch");
+            }
+            "#,
+        );
+
+        deno.execute_script("[lyra:batch.js]", check)
+            .map_err(Error::reducer)
+            .or_panic()?;
+    }
+
+    Ok((deno, loader.loaded()))
+}
+
+/// What the worker feeds into the reducer: a single event, or a run of
+/// already queued blocks that go through `applyBatch` in one call.
+pub enum WorkUnit {
+    Event(ChainEvent),
+    Batch(Vec<ChainEvent>),
+    /// an undo left by a rewind, it goes to storage as is
+    Forward(ChainEvent),
+}
+
+fn to_record(
+    stage: &Stage,
+    module: &Module,
+    json: serde_json::Value,
+) -> Result<Record, WorkerError> {
+    let record = match stage.storage_type.as_str() {
+        "None" => Record::None,
+        "Redis" => {
+            let commands: Vec<CRDTCommand> = CRDTCommand::from_json_array(&json).or_panic()?;
+
+            let commands = match &module.prefix {
+                Some(prefix) => commands
+                    .into_iter()
+                    .map(|x| x.with_prefix(prefix))
+                    .collect(),
+                None => commands,
+            };
+
+            Record::CRDTCommand(commands)
+        }
+        "Postgres" => {
+            let commands: Vec<String> = serde_json::from_value(json).or_panic()?;
+
+            // modules share the storage transaction, so once any of them
+            // targets a schema each one has to set its own
+            let search_path = match &module.schema {
+                Some(schema) => Some(format!("SET LOCAL search_path TO \"{}\"", schema)),
+                None if stage.modules.iter().any(|x| x.schema.is_some()) => {
+                    Some("SET LOCAL search_path TO DEFAULT".to_string())
+                }
+                None => None,
+            };
+
+            Record::SQLCommand(search_path.into_iter().chain(commands).collect())
+        }
+        _ => return Err(WorkerError::Panic),
+    };
+
+    Ok(record)
+}
+
+/// Merges the records of every module for the same event, so that the
+/// storage applies them in a single transaction.
+fn merge_records(records: Vec<Record>) -> Option<Record> {
+    let mut records = records.into_iter();
+    let first = records.next()?;
+
+    let merged = records.fold(first, |merged, record| match (merged, record) {
+        (Record::CRDTCommand(mut all), Record::CRDTCommand(x)) => {
+            all.extend(x);
+            Record::CRDTCommand(all)
+        }
+        (Record::SQLCommand(mut all), Record::SQLCommand(x)) => {
+            all.extend(x);
+            Record::SQLCommand(all)
+        }
+        (merged, _) => merged,
+    });
+
+    Some(merged)
+}
+
+#[derive(Stage)]
+#[stage(name = "reducer-deno", unit = "WorkUnit", worker = "Worker")]
+pub struct Stage {
+    modules: Vec<Module>,
+    import_map: Option<ImportMap>,
+    permissions: PermissionsConfig,
+    timeout: Option<Duration>,
+    max_heap_mb: Option<usize>,
+    state_path: Option<PathBuf>,
+    batch_size: Option<usize>,
+    hot_reload: Option<HotReloadConfig>,
+    restart: RestartSignal,
+    storage_type: String,
+    chain: ChainValues,
+    /// event that ended the last batch, processed on the next schedule. It
+    /// lives on the stage so that it survives a restart of the worker.
+    pending: Option<ChainEvent>,
+    /// undo events left by a rewind, sent to storage before any block
+    undo: VecDeque<ChainEvent>,
+    /// storage records of the latest blocks, newest last, so that a rewind
+    /// can undo them
+    history: VecDeque<(Point, Record)>,
+    /// slot from which on every block sent to storage is in the history,
+    /// unknown when the pipeline started at the tip
+    history_start: Option<u64>,
+    call_snippet: &'static str,
+
+    pub input: ReducerInputPort,
+    pub output: ReducerOutputPort,
+
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+}
+
+impl Stage {
+    fn keeps_history(&self) -> bool {
+        self.hot_reload.as_ref().is_some_and(|x| x.rewind.is_some())
+    }
+
+    /// Keeps track of what the storage got for a block, so that a rewind can
+    /// take it back later.
+    fn remember(&mut self, unit: &ChainEvent, record: &Record) {
+        if !self.keeps_history() {
+            return;
+        }
+
+        match unit {
+            ChainEvent::Apply(point, _) => {
+                if self.history_start.is_none() {
+                    self.history_start = Some(point.slot_or_default());
+                }
+
+                self.history.push_back((point.clone(), record.clone()));
+
+                while self.history.len() > MAX_JOURNAL_BLOCKS {
+                    if let Some((point, _)) = self.history.pop_front() {
+                        self.history_start = Some(point.slot_or_default() + 1);
+                    }
+                }
+            }
+            ChainEvent::Undo(point, _) => {
+                if self.history.back().is_some_and(|(x, _)| x == point) {
+                    self.history.pop_back();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// The undo events that take the storage back to the given slot, newest
+    /// first. None if some block after it isn't in the history anymore or
+    /// its commands can't be inverted.
+    fn rewind_events(&self, slot: u64) -> Option<Vec<ChainEvent>> {
+        if self.history_start? > slot {
+            return None;
+        }
+
+        self.history
+            .iter()
+            .rev()
+            .take_while(|(point, _)| point.slot_or_default() >= slot)
+            .map(|(point, record)| {
+                let record = match record {
+                    Record::CRDTCommand(x) => Record::CRDTCommand(
+                        x.iter()
+                            .rev()
+                            .cloned()
+                            .map(CRDTCommand::invert)
+                            .collect::<Option<_>>()?,
+                    ),
+                    Record::None => Record::None,
+                    _ => return None,
+                };
+
+                Some(ChainEvent::Undo(point.clone(), record))
+            })
+            .collect()
+    }
+}
+
+pub struct Worker {
+    runtime: DenoWorker,
+    /// the call snippet, compiled once at bootstrap
+    call: v8::Global<v8::Function>,
+    watchdog: Option<Watchdog>,
+    heap_exceeded: Arc<AtomicBool>,
+    /// module files along with their last modification time, for hot reload
+    modules: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Worker {
+    fn modules_changed(&self) -> bool {
+        self.modules
+            .iter()
+            .any(|(path, mtime)| modified(path) != *mtime)
+    }
+
+    /// Waits for the next event. With hot reload enabled it also polls the
+    /// reducer modules, returning `None` once the pipeline should start over.
+    async fn next_event(&mut self, stage: &mut Stage) -> Result<Option<ChainEvent>, WorkerError> {
+        let hot_reload = match &stage.hot_reload {
+            Some(x) => x.clone(),
+            None => return Ok(Some(stage.input.recv().await.or_panic()?.payload)),
+        };
+
+        loop {
+            let msg = tokio::select! {
+                msg = stage.input.recv() => Some(msg),
+                _ = tokio::time::sleep(hot_reload.poll_interval()) => None,
+            };
+
+            if let Some(msg) = msg {
+                return Ok(Some(msg.or_panic()?.payload));
+            }
+
+            if !self.modules_changed() {
+                continue;
+            }
+
+            let undo = hot_reload
+                .rewind_slot()
+                .and_then(|slot| stage.rewind_events(slot));
+
+            match (&hot_reload.rewind, undo) {
+                (Some(rewind), Some(undo)) => {
+                    info!(
+                        "reducer module changed, undoing {} blocks and restarting from {:?}",
+                        undo.len(),
+                        rewind
+                    );
+                    stage.restart.request(rewind.clone(), undo);
+                    return Ok(None);
+                }
+                (Some(rewind), None) => {
+                    // replaying on top of what the storage already has would
+                    // count every block twice
+                    error!(
+                        "reducer module changed, but the storage can't be taken back to {:?}, reloading without rewinding",
+                        rewind
+                    );
+                    return Err(WorkerError::Restart);
+                }
+                (None, _) => {
+                    // the worker is bootstrapped again, which loads the new module
+                    info!("reducer module changed, reloading");
+                    return Err(WorkerError::Restart);
+                }
+            }
+        }
+    }
+
+    fn with_state<T>(
+        &mut self,
+        f: impl FnOnce(&mut State) -> Result<T, Error>,
+    ) -> Result<Option<T>, WorkerError> {
+        let op_state = self.runtime.js_runtime.op_state();
+        let mut op_state = op_state.borrow_mut();
+
+        match op_state.try_borrow_mut::<State>() {
+            Some(state) => f(state).map(Some).or_panic(),
+            None => Ok(None),
+        }
+    }
+
+    fn invoke(&mut self, module: usize, method: &str) -> Result<(), deno_core::error::AnyError> {
+        let scope = &mut self.runtime.js_runtime.handle_scope();
+
+        let call = v8::Local::new(scope, &self.call);
+        let recv: v8::Local<v8::Value> = v8::undefined(scope).into();
+        let module: v8::Local<v8::Value> =
+            v8::Integer::new_from_unsigned(scope, module as u32).into();
+        let method: v8::Local<v8::Value> = v8::String::new(scope, method)
+            .ok_or_else(|| deno_core::anyhow::anyhow!("can't allocate method name"))?
+            .into();
+
+        let scope = &mut v8::TryCatch::new(scope);
+        call.call(scope, recv, &[module, method]);
+
+        if scope.has_terminated() {
+            deno_core::anyhow::bail!("execution terminated");
+        }
+
+        if let Some(exception) = scope.exception() {
+            return Err(deno_core::error::JsError::from_v8_exception(scope, exception).into());
+        }
+
+        Ok(())
+    }
+
+    async fn call(
+        &mut self,
+        module: usize,
+        method: &str,
+        deadline: Option<Instant>,
+    ) -> Result<(), deno_core::error::AnyError> {
+        self.invoke(module, method)?;
+
+        let deno = &mut self.runtime;
+
+        match deadline {
+            // pending promises don't run any JS, so the watchdog can't see them
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), deno.run_event_loop(false))
+                .await
+                .map_err(|_| deno_core::anyhow::anyhow!("event loop didn't settle"))?,
+            None => deno.run_event_loop(false).await,
+        }
+    }
+
+    async fn reduce(
+        &mut self,
+        stage: &Stage,
+        module: usize,
+        method: &str,
+        input: serde_json::Value,
+        context: serde_json::Value,
+    ) -> Result<Option<serde_json::Value>, WorkerError> {
+        {
+            let op_state = self.runtime.js_runtime.op_state();
+            let mut op_state = op_state.borrow_mut();
+            op_state.put(ReducerInput(input));
+            op_state.put(ReducerContext(context));
+        }
+
+        let deadline = stage.timeout.map(|x| Instant::now() + x);
+
+        if let (Some(watchdog), Some(deadline)) = (&self.watchdog, deadline) {
+            watchdog.arm(deadline);
+        }
+
+        let result = self.call(module, method, deadline).await;
+
+        let timed_out = self
+            .watchdog
+            .as_ref()
+            .map(Watchdog::disarm)
+            .unwrap_or_default()
+            || deadline.is_some_and(|x| Instant::now() >= x);
+
+        if let Err(err) = result {
+            let err = if self.heap_exceeded.load(Ordering::SeqCst) {
+                Error::reducer(format!(
+                    "heap limit of {}MB exceeded",
+                    stage.max_heap_mb.unwrap_or_default()
+                ))
+            } else if timed_out {
+                Error::reducer(format!(
+                    "timeout of {}ms exceeded",
+                    stage.timeout.unwrap_or_default().as_millis()
+                ))
+            } else {
+                Error::reducer(err)
+            };
+
+            return Err(err).or_panic();
+        }
+
+        let output: Option<serde_json::Value> =
+            self.runtime.js_runtime.op_state().borrow_mut().try_take();
+
+        Ok(output)
+    }
+
+    /// Runs the event through every module, merging their outputs.
+    async fn reduce_all(
+        &mut self,
+        stage: &Stage,
+        method: &str,
+        input: serde_json::Value,
+        context: serde_json::Value,
+    ) -> Result<Option<Record>, WorkerError> {
+        let mut records = Vec::with_capacity(stage.modules.len());
+
+        for (index, module) in stage.modules.iter().enumerate() {
+            self.with_state(|state| {
+                state.namespace(module.prefix.clone());
+                Ok(())
+            })?;
+
+            let output = self
+                .reduce(stage, index, method, input.clone(), context.clone())
+                .await?;
+
+            if let Some(json) = output {
+                records.push(to_record(stage, module, json)?);
+            }
+        }
+
+        Ok(merge_records(records))
+    }
+
+    /// Runs a batch of blocks through `applyBatch` of every module, merging
+    /// their outputs block by block.
+    async fn reduce_batch(
+        &mut self,
+        stage: &Stage,
+        blocks: Vec<serde_json::Value>,
+        contexts: Vec<serde_json::Value>,
+    ) -> Result<Vec<Option<Record>>, WorkerError> {
+        let count = blocks.len();
+        let blocks = json!(blocks);
+        let contexts = json!(contexts);
+
+        let mut records: Vec<Vec<Record>> = (0..count).map(|_| vec![]).collect();
+
+        for (index, module) in stage.modules.iter().enumerate() {
+            let outputs = match self
+                .reduce(stage, index, "applyBatch", blocks.clone(), contexts.clone())
+                .await?
+            {
+                None => continue,
+                Some(serde_json::Value::Array(x)) if x.len() == count => x,
+                Some(_) => {
+                    return Err(Error::reducer(format!(
+                        "applyBatch must return an array with one output per block ({})",
+                        count
+                    )))
+                    .or_panic();
+                }
+            };
+
+            for (block, json) in records.iter_mut().zip(outputs) {
+                if !json.is_null() {
+                    block.push(to_record(stage, module, json)?);
+                }
+            }
+        }
+
+        Ok(records.into_iter().map(merge_records).collect())
+    }
+
+    async fn execute_event(
+        &mut self,
+        unit: &ChainEvent,
+        stage: &mut Stage,
+    ) -> Result<(), WorkerError> {
+        if let ChainEvent::Reset(point) = unit {
+            // n2n and n2c report rollbacks as resets only, whatever was applied
+            // past the point belongs to orphaned blocks
+            self.with_state(|state| state.rewind_after(point))?;
+        }
+
+        let (method, input, context) = match super::to_call(unit, &stage.chain)? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        if let ChainEvent::Apply(point, _) = unit {
+            // a block that's already in the journal is being replayed after a
+            // restart, the state goes back to how it was before it
+            self.with_state(|state| state.rewind(point))?;
+        }
+
+        self.with_state(|state| {
+            state.begin(matches!(unit, ChainEvent::Apply(..)));
+            Ok(())
+        })?;
+
+        let record = self.reduce_all(stage, method, input, context).await?;
+
+        match unit {
+            ChainEvent::Apply(point, _) => self.with_state(|state| state.commit(point))?,
+            ChainEvent::Undo(point, _) => self.with_state(|state| state.rewind(point))?,
+            _ => None,
+        };
+
+        // blocks are sent even without output, storage has to track them all
+        let record = match unit {
+            ChainEvent::Apply(..) | ChainEvent::Undo(..) => {
+                Some(record.unwrap_or_else(|| super::empty_record(&stage.storage_type)))
+            }
+            _ => record,
+        };
+
+        if let Some(record) = record {
+            stage.remember(unit, &record);
+
+            stage
+                .output
+                .send(unit.with_record(record))
+                .await
+                .or_retry()?;
+        }
+
+        match unit {
+            ChainEvent::Apply(point, _) => info!("Processed apply for block {:?}", point),
+            ChainEvent::Undo(point, _) => info!("Processed undo for block {:?}", point),
+            _ => info!("Processed {} for mempool tx", method),
+        }
+
+        Ok(())
+    }
+
+    async fn execute_batch(
+        &mut self,
+        units: &[ChainEvent],
+        stage: &mut Stage,
+    ) -> Result<(), WorkerError> {
+        let mut applied = Vec::with_capacity(units.len());
+        let mut blocks = Vec::with_capacity(units.len());
+        let mut contexts = Vec::with_capacity(units.len());
+
+        for unit in units {
+            if let Some((_, input, context)) = super::to_call(unit, &stage.chain)? {
+                applied.push(unit);
+                blocks.push(input);
+                contexts.push(context);
+            }
+        }
+
+        if applied.is_empty() {
+            return Ok(());
+        }
+
+        let records = self.reduce_batch(stage, blocks, contexts).await?;
+
+        // each block goes to storage on its own, so that it tracks every
+        // point and a rollback can stop anywhere within the batch
+        for (unit, record) in applied.iter().zip(records) {
+            let record = record.unwrap_or_else(|| super::empty_record(&stage.storage_type));
+            stage.remember(unit, &record);
+
+            stage
+                .output
+                .send(unit.with_record(record))
+                .await
+                .or_retry()?;
+        }
+
+        info!(
+            "Processed apply for {} blocks up to {:?}",
+            applied.len(),
+            applied.last().and_then(|x| x.point())
+        );
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        let (mut runtime, modules) = setup_deno(stage).await?;
+
+        let call = runtime
+            .execute_script(
+                "[lyra:call.js]",
+                deno_core::FastString::from_static(stage.call_snippet),
+            )
+            .map_err(Error::reducer)
+            .or_panic()?;
+
+        let call = {
+            let scope = &mut runtime.js_runtime.handle_scope();
+            let call = v8::Local::new(scope, call);
+            let call = v8::Local::<v8::Function>::try_from(call)
+                .map_err(Error::reducer)
+                .or_panic()?;
+            v8::Global::new(scope, call)
+        };
+
+        let isolate = runtime.js_runtime.v8_isolate().thread_safe_handle();
+        let watchdog = stage.timeout.map(|_| Watchdog::spawn(isolate.clone()));
+
+        let heap_exceeded = Arc::new(AtomicBool::new(false));
+
+        if stage.max_heap_mb.is_some() {
+            let flag = heap_exceeded.clone();
+
+            runtime
+                .js_runtime
+                .add_near_heap_limit_callback(move |current, _| {
+                    flag.store(true, Ordering::SeqCst);
+                    isolate.terminate_execution();
+                    // leave v8 enough room to unwind instead of aborting the process
+                    current * 2
+                });
+        }
+
+        Ok(Self {
+            runtime,
+            call,
+            watchdog,
+            heap_exceeded,
+            modules: modules
+                .into_iter()
+                .map(|x| (x.clone(), modified(&x)))
+                .collect(),
+        })
+    }
+
+    async fn schedule(&mut self, stage: &mut Stage) -> Result<WorkSchedule<WorkUnit>, WorkerError> {
+        // the storage is taken back to the rewind point before anything else
+        if let Some(x) = stage.undo.pop_front() {
+            return Ok(WorkSchedule::Unit(WorkUnit::Forward(x)));
+        }
+
+        // the event that ended the previous batch goes first, before any
+        // block queued after it
+        let next = match stage.pending.take() {
+            Some(x) => x,
+            None => match self.next_event(stage).await? {
+                Some(x) => x,
+                None => return Ok(WorkSchedule::Done),
+            },
+        };
+
+        let batch_size = match stage.batch_size {
+            Some(x) if matches!(next, ChainEvent::Apply(..)) => x,
+            _ => return Ok(WorkSchedule::Unit(WorkUnit::Event(next))),
+        };
+
+        let mut batch = vec![next];
+
+        // only blocks that are already queued join the batch, so at the tip,
+        // where rollbacks happen, blocks are still applied one at a time
+        while batch.len() < batch_size {
+            let msg = match tokio::time::timeout(Duration::ZERO, stage.input.recv()).await {
+                Ok(msg) => msg.or_panic()?,
+                Err(_) => break,
+            };
+
+            match msg.payload {
+                x @ ChainEvent::Apply(..) => batch.push(x),
+                x => {
+                    stage.pending = Some(x);
+                    break;
+                }
+            }
+        }
+
+        if batch.len() == 1 {
+            return Ok(WorkSchedule::Unit(WorkUnit::Event(batch.remove(0))));
+        }
+
+        Ok(WorkSchedule::Unit(WorkUnit::Batch(batch)))
+    }
+
+    async fn execute(&mut self, unit: &WorkUnit, stage: &mut Stage) -> Result<(), WorkerError> {
+        match unit {
+            WorkUnit::Event(x) => self.execute_event(x, stage).await,
+            WorkUnit::Batch(x) => self.execute_batch(x, stage).await,
+            WorkUnit::Forward(x) => {
+                stage
+                    .output
+                    .send(gasket::messaging::Message { payload: x.clone() })
+                    .await
+                    .or_retry()?;
+
+                info!("Processed rewind undo for block {:?}", x.point());
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+
+use deno_runtime::deno_core::anyhow::{anyhow, bail};
+use deno_runtime::deno_core::error::AnyError;
+use deno_runtime::deno_core::op2;
+use deno_runtime::deno_core::OpState;
+use pallas::network::miniprotocols::Point;
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::Transactional;
+
+use crate::framework::errors::Error;
+
+/// how many applied blocks keep their journal around, rollbacks can't go
+/// deeper than the security param k
+pub const MAX_JOURNAL_BLOCKS: usize = 2160;
+
+/// a journal entry: every key the block wrote, along with the json it had
+/// before the block (none if it didn't exist)
+type JournalEntry = Vec<(String, Option<String>)>;
+
+fn point_key(point: &Point) -> Vec<u8> {
+    match point {
+        Point::Origin => 0u64.to_be_bytes().to_vec(),
+        Point::Specific(slot, hash) => {
+            let mut key = slot.to_be_bytes().to_vec();
+            key.extend_from_slice(hash);
+            key
+        }
+    }
+}
+
+fn to_error(err: TransactionError<Error>) -> Error {
+    match err {
+        TransactionError::Abort(x) => x,
+        TransactionError::Storage(x) => Error::storage(x),
+    }
+}
+
+/// Key / value state that survives between blocks. Writes of the block being
+/// applied are buffered and committed along with a journal entry, so they can
+/// be undone when the block is rolled back.
+pub struct State {
+    values: sled::Tree,
+    journal: sled::Tree,
+    journal_len: usize,
+    pending: BTreeMap<String, Option<String>>,
+    writable: bool,
+    /// prefix of the module currently running, keeps modules from seeing
+    /// each other's keys
+    namespace: Option<String>,
+}
+
+impl State {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let db = sled::open(path).map_err(Error::storage)?;
+        let values = db.open_tree("values").map_err(Error::storage)?;
+        let journal = db.open_tree("journal").map_err(Error::storage)?;
+        let journal_len = journal.len();
+
+        Ok(Self {
+            values,
+            journal,
+            journal_len,
+            pending: Default::default(),
+            writable: false,
+            namespace: None,
+        })
+    }
+
+    /// starts a new call into the reducer, only `apply` may write
+    pub fn begin(&mut self, writable: bool) {
+        self.pending.clear();
+        self.writable = writable;
+    }
+
+    pub fn namespace(&mut self, prefix: Option<String>) {
+        self.namespace = prefix;
+    }
+
+    fn key(&self, key: &str) -> String {
+        match &self.namespace {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key.to_string(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<serde_json::Value>, AnyError> {
+        let key = self.key(key);
+
+        let json = match self.pending.get(&key) {
+            Some(x) => x.clone(),
+            None => self
+                .values
+                .get(&key)?
+                .map(|x| String::from_utf8_lossy(&x).into_owned()),
+        };
+
+        Ok(json.map(|x| serde_json::from_str(&x)).transpose()?)
+    }
+
+    fn put(&mut self, key: String, value: Option<serde_json::Value>) -> Result<(), AnyError> {
+        if !self.writable {
+            bail!("reducer state can only be written from apply, undo rolls it back on its own");
+        }
+
+        let json = value.map(|x| x.to_string());
+        self.pending.insert(self.key(&key), json);
+
+        Ok(())
+    }
+
+    /// writes the pending changes of the block at `point`
+    pub fn commit(&mut self, point: &Point) -> Result<(), Error> {
+        let pending = std::mem::take(&mut self.pending);
+        self.writable = false;
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let key = point_key(point);
+
+        (&self.values, &self.journal)
+            .transaction(|(values, journal)| {
+                let mut entry: JournalEntry = Vec::with_capacity(pending.len());
+
+                for (k, v) in pending.iter() {
+                    let previous = match v {
+                        Some(json) => values.insert(k.as_bytes(), json.as_bytes())?,
+                        None => values.remove(k.as_bytes())?,
+                    };
+
+                    let previous = previous.map(|x| String::from_utf8_lossy(&x).into_owned());
+                    entry.push((k.clone(), previous));
+                }
+
+                let entry = serde_json::to_vec(&entry)
+                    .map_err(|e| ConflictableTransactionError::Abort(Error::serialization(e)))?;
+
+                journal.insert(key.as_slice(), entry)?;
+
+                Ok(())
+            })
+            .map_err(to_error)?;
+
+        self.journal_len += 1;
+
+        while self.journal_len > MAX_JOURNAL_BLOCKS {
+            self.journal.pop_min().map_err(Error::storage)?;
+            self.journal_len -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// restores the state as it was before the block at `point`, undoing any
+    /// block applied after it too
+    pub fn rewind(&mut self, point: &Point) -> Result<(), Error> {
+        self.undo_from(Bound::Included(point_key(point)))
+    }
+
+    /// restores the state as it was right after the block at `point`, undoing
+    /// every block applied after it (eg: on a chain reset to that point)
+    pub fn rewind_after(&mut self, point: &Point) -> Result<(), Error> {
+        self.undo_from(Bound::Excluded(point_key(point)))
+    }
+
+    fn undo_from(&mut self, start: Bound<Vec<u8>>) -> Result<(), Error> {
+        self.pending.clear();
+        self.writable = false;
+
+        let entries = self
+            .journal
+            .range((start, Bound::Unbounded))
+            .rev()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::storage)?;
+
+        for (key, entry) in entries {
+            let entry: JournalEntry = serde_json::from_slice(&entry).map_err(Error::parsing)?;
+
+            (&self.values, &self.journal)
+                .transaction(|(values, journal)| {
+                    for (k, previous) in entry.iter().rev() {
+                        match previous {
+                            Some(json) => values.insert(k.as_bytes(), json.as_bytes())?,
+                            None => values.remove(k.as_bytes())?,
+                        };
+                    }
+
+                    journal.remove(key.clone())?;
+
+                    Ok::<_, ConflictableTransactionError<Error>>(())
+                })
+                .map_err(to_error)?;
+
+            self.journal_len = self.journal_len.saturating_sub(1);
+        }
+
+        Ok(())
+    }
+}
+
+fn borrow_state(state: &mut OpState) -> Result<&mut State, AnyError> {
+    state
+        .try_borrow_mut::<State>()
+        .ok_or_else(|| anyhow!("reducer state isn't enabled, set `state_path` in the config"))
+}
+
+#[op2]
+#[serde]
+pub fn op_state_get(
+    state: &mut OpState,
+    #[string] key: String,
+) -> Result<Option<serde_json::Value>, AnyError> {
+    borrow_state(state)?.get(&key)
+}
+
+#[op2]
+pub fn op_state_set(
+    state: &mut OpState,
+    #[string] key: String,
+    #[serde] value: serde_json::Value,
+) -> Result<(), AnyError> {
+    borrow_state(state)?.put(key, Some(value))
+}
+
+#[op2]
+pub fn op_state_delete(state: &mut OpState, #[string] key: String) -> Result<(), AnyError> {
+    borrow_state(state)?.put(key, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn temporary() -> State {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+
+        State {
+            values: db.open_tree("values").unwrap(),
+            journal: db.open_tree("journal").unwrap(),
+            journal_len: 0,
+            pending: Default::default(),
+            writable: false,
+            namespace: None,
+        }
+    }
+
+    fn point(slot: u64) -> Point {
+        Point::Specific(slot, vec![slot as u8; 32])
+    }
+
+    fn apply(state: &mut State, slot: u64, writes: &[(&str, Option<serde_json::Value>)]) {
+        state.begin(true);
+
+        for (key, value) in writes {
+            state.put(key.to_string(), value.clone()).unwrap();
+        }
+
+        state.commit(&point(slot)).unwrap();
+    }
+
+    fn value(state: &State, key: &str) -> Option<serde_json::Value> {
+        state.get(key).unwrap()
+    }
+
+    #[test]
+    fn writes_are_read_back() {
+        let mut state = temporary();
+
+        state.begin(true);
+        state.put("a".into(), Some(json!(1))).unwrap();
+        assert_eq!(value(&state, "a"), Some(json!(1)));
+
+        state.commit(&point(10)).unwrap();
+        assert_eq!(value(&state, "a"), Some(json!(1)));
+    }
+
+    #[test]
+    fn only_apply_writes() {
+        let mut state = temporary();
+
+        state.begin(false);
+        assert!(state.put("a".into(), Some(json!(1))).is_err());
+    }
+
+    #[test]
+    fn blocks_without_writes_are_not_journaled() {
+        let mut state = temporary();
+
+        apply(&mut state, 10, &[]);
+
+        assert_eq!(state.journal_len, 0);
+        assert!(state.journal.is_empty());
+    }
+
+    #[test]
+    fn namespaces_are_isolated() {
+        let mut state = temporary();
+
+        state.namespace(Some("one".into()));
+        apply(&mut state, 10, &[("a", Some(json!(1)))]);
+
+        state.namespace(Some("two".into()));
+        assert_eq!(value(&state, "a"), None);
+
+        state.namespace(Some("one".into()));
+        assert_eq!(value(&state, "a"), Some(json!(1)));
+    }
+
+    #[test]
+    fn rewind_undoes_the_block_and_the_ones_after() {
+        let mut state = temporary();
+
+        apply(&mut state, 10, &[("a", Some(json!(1)))]);
+        apply(
+            &mut state,
+            20,
+            &[("a", Some(json!(2))), ("b", Some(json!(2)))],
+        );
+        apply(&mut state, 30, &[("a", None), ("c", Some(json!(3)))]);
+
+        state.rewind(&point(20)).unwrap();
+
+        assert_eq!(value(&state, "a"), Some(json!(1)));
+        assert_eq!(value(&state, "b"), None);
+        assert_eq!(value(&state, "c"), None);
+        assert_eq!(state.journal_len, 1);
+        assert_eq!(state.journal.len(), 1);
+
+        // the blocks before the point are kept
+        state.rewind(&point(10)).unwrap();
+
+        assert_eq!(value(&state, "a"), None);
+        assert!(state.journal.is_empty());
+    }
+
+    #[test]
+    fn rewind_between_blocks() {
+        let mut state = temporary();
+
+        apply(&mut state, 10, &[("a", Some(json!(1)))]);
+        apply(&mut state, 20, &[("a", Some(json!(2)))]);
+
+        // a point without writes of its own undoes the blocks after it
+        state.rewind(&point(15)).unwrap();
+        assert_eq!(value(&state, "a"), Some(json!(1)));
+
+        state.rewind(&point(25)).unwrap();
+        assert_eq!(value(&state, "a"), Some(json!(1)));
+
+        state.rewind(&Point::Origin).unwrap();
+        assert_eq!(value(&state, "a"), None);
+    }
+
+    #[test]
+    fn rewind_after_a_reset() {
+        let mut state = temporary();
+
+        apply(&mut state, 90, &[("a", Some(json!(90)))]);
+        apply(
+            &mut state,
+            100,
+            &[("a", Some(json!(100))), ("b", Some(json!(100)))],
+        );
+        apply(&mut state, 105, &[("c", Some(json!(105)))]);
+
+        // the blocks past the reset point are orphaned, the reset one stays
+        state.rewind_after(&point(90)).unwrap();
+
+        assert_eq!(value(&state, "a"), Some(json!(90)));
+        assert_eq!(value(&state, "b"), None);
+        assert_eq!(value(&state, "c"), None);
+        assert_eq!(state.journal_len, 1);
+
+        apply(&mut state, 103, &[("b", Some(json!(103)))]);
+
+        assert_eq!(value(&state, "a"), Some(json!(90)));
+        assert_eq!(value(&state, "b"), Some(json!(103)));
+
+        state.rewind_after(&Point::Origin).unwrap();
+
+        assert_eq!(value(&state, "a"), None);
+        assert_eq!(value(&state, "b"), None);
+        assert!(state.journal.is_empty());
+    }
+
+    #[test]
+    fn rewind_drops_pending_writes() {
+        let mut state = temporary();
+
+        apply(&mut state, 10, &[("a", Some(json!(1)))]);
+
+        state.begin(true);
+        state.put("a".into(), Some(json!(2))).unwrap();
+        state.rewind(&point(20)).unwrap();
+
+        assert_eq!(value(&state, "a"), Some(json!(1)));
+        assert!(state.put("a".into(), Some(json!(3))).is_err());
+    }
+
+    #[test]
+    fn journal_is_pruned() {
+        let mut state = temporary();
+        let blocks = MAX_JOURNAL_BLOCKS as u64 + 2;
+
+        for slot in 1..=blocks {
+            apply(&mut state, slot, &[("a", Some(json!(slot)))]);
+        }
+
+        assert_eq!(state.journal_len, MAX_JOURNAL_BLOCKS);
+        assert_eq!(state.journal.len(), MAX_JOURNAL_BLOCKS);
+
+        // the oldest blocks can't be undone anymore
+        state.rewind(&point(1)).unwrap();
+
+        assert_eq!(value(&state, "a"), Some(json!(2)));
+        assert!(state.journal.is_empty());
+    }
+}
@@ -1,13 +1,25 @@
+use gasket::framework::*;
 use gasket::runtime::Tether;
+use pallas::interop::utxorpc::Mapper;
+use pallas::ledger::traverse::MultiEraBlock;
+use pallas::network::miniprotocols::Point;
 use serde::Deserialize;
+use serde_json::json;
+use utxorpc_spec::utxorpc::v1alpha::cardano as u5c;
 
+use crate::framework::model::{BlockContext, CRDTCommand};
 use crate::framework::{errors::Error, *};
 
 pub mod builtin;
 pub mod deno;
+pub mod process;
+pub mod wasm;
+
 pub enum Bootstrapper {
     BuiltIn(builtin::Stage),
     Deno(deno::Stage),
+    Process(process::Stage),
+    Wasm(wasm::Stage),
 }
 
 impl Bootstrapper {
@@ -15,6 +27,8 @@ impl Bootstrapper {
         match self {
             Bootstrapper::BuiltIn(p) => &mut p.output,
             Bootstrapper::Deno(p) => &mut p.output,
+            Bootstrapper::Process(p) => &mut p.output,
+            Bootstrapper::Wasm(p) => &mut p.output,
         }
     }
 
@@ -22,6 +36,8 @@ impl Bootstrapper {
         match self {
             Bootstrapper::BuiltIn(p) => &mut p.input,
             Bootstrapper::Deno(p) => &mut p.input,
+            Bootstrapper::Process(p) => &mut p.input,
+            Bootstrapper::Wasm(p) => &mut p.input,
         }
     }
 
@@ -29,6 +45,8 @@ impl Bootstrapper {
         match self {
             Bootstrapper::BuiltIn(s) => gasket::runtime::spawn_stage(s, policy),
             Bootstrapper::Deno(s) => gasket::runtime::spawn_stage(s, policy),
+            Bootstrapper::Process(s) => gasket::runtime::spawn_stage(s, policy),
+            Bootstrapper::Wasm(s) => gasket::runtime::spawn_stage(s, policy),
         }
     }
 }
@@ -38,6 +56,8 @@ impl Bootstrapper {
 pub enum Config {
     BuiltIn(builtin::Config),
     Deno(deno::Config),
+    Process(process::Config),
+    Wasm(wasm::Config),
 }
 
 impl Config {
@@ -45,6 +65,141 @@ impl Config {
         match self {
             Config::BuiltIn(c) => Ok(Bootstrapper::BuiltIn(c.bootstrapper(ctx)?)),
             Config::Deno(c) => Ok(Bootstrapper::Deno(c.bootstrapper(ctx)?)),
+            Config::Process(c) => Ok(Bootstrapper::Process(c.bootstrapper(ctx)?)),
+            Config::Wasm(c) => Ok(Bootstrapper::Wasm(c.bootstrapper(ctx)?)),
         }
     }
 }
+
+/// Where a block sits in the chain, handed to scripted reducers along with
+/// the block itself.
+fn block_context(point: &Point, rollback: bool, chain: &ChainValues) -> serde_json::Value {
+    let (slot, hash) = match point {
+        Point::Origin => (0, None),
+        Point::Specific(slot, hash) => (*slot, Some(hex::encode(hash))),
+    };
+
+    let (epoch, epoch_slot) = chain.genesis.absolute_slot_to_relative(slot);
+
+    json!({
+        "point": { "slot": slot, "hash": hash },
+        "rollback": rollback,
+        "chain": {
+            "magic": chain.genesis.magic,
+            "network_id": chain.genesis.address_network_id,
+        },
+        "time": {
+            "epoch": epoch,
+            "epoch_slot": epoch_slot,
+            "timestamp": chain.slot_to_wallclock(slot),
+        },
+    })
+}
+
+fn to_u5c(record: &Record) -> Result<Option<u5c::Block>, WorkerError> {
+    let (cbor, mut ctx) = match record {
+        Record::UtxoRpcBlockPayload(block) => return Ok(Some(block.clone())),
+        Record::RawBlockPayload(cbor) => (cbor, BlockContext::default()),
+        Record::EnrichedBlockPayload(cbor, ctx) => (cbor, ctx.clone()),
+        _ => return Ok(None),
+    };
+
+    let block = MultiEraBlock::decode(cbor)
+        .map_err(Error::cbor)
+        .or_panic()?;
+
+    // outputs spent within the block they're produced in are always known
+    ctx.import_block_outputs(&block);
+
+    Ok(Some(Mapper::new(ctx).map_block(&block)))
+}
+
+/// Same as `block_context`, along with the outputs the block consumes that
+/// could be resolved: the ones produced earlier in the same block, plus the
+/// ones the source enriched the block with.
+fn u5c_block_context(
+    point: &Point,
+    rollback: bool,
+    block: &u5c::Block,
+    chain: &ChainValues,
+) -> serde_json::Value {
+    let consumed: serde_json::Map<_, _> = block
+        .body
+        .iter()
+        .flat_map(|body| body.tx.iter())
+        .flat_map(|tx| tx.inputs.iter())
+        .filter_map(|input| {
+            input.as_output.as_ref().map(|output| {
+                let key = format!("{}#{}", hex::encode(&input.tx_hash), input.output_index);
+                (key, json!(output))
+            })
+        })
+        .collect();
+
+    let mut context = block_context(point, rollback, chain);
+    context["consumed"] = json!(consumed);
+    context
+}
+
+/// Maps an event to the scripted reducer method it calls, along with its
+/// input and context arguments.
+fn to_call(
+    unit: &ChainEvent,
+    chain: &ChainValues,
+) -> Result<Option<(&'static str, serde_json::Value, serde_json::Value)>, WorkerError> {
+    let call = match unit {
+        ChainEvent::Apply(point, record) | ChainEvent::Undo(point, record) => {
+            let block = match to_u5c(record)? {
+                Some(x) => x,
+                None => return Ok(None),
+            };
+
+            let rollback = matches!(unit, ChainEvent::Undo(..));
+            let context = u5c_block_context(point, rollback, &block, chain);
+            let method = if rollback { "undo" } else { "apply" };
+
+            (method, json!(block), context)
+        }
+        ChainEvent::MempoolAdd(hash, Record::RawTxPayload(era, cbor)) => (
+            "mempool",
+            json!({ "action": "add", "hash": hash, "era": era, "cbor": hex::encode(cbor) }),
+            serde_json::Value::Null,
+        ),
+        ChainEvent::MempoolRemove(hash, _) => (
+            "mempool",
+            json!({ "action": "remove", "hash": hash }),
+            serde_json::Value::Null,
+        ),
+        ChainEvent::MempoolConfirm(hash, _) => (
+            "mempool",
+            json!({ "action": "confirm", "hash": hash }),
+            serde_json::Value::Null,
+        ),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(call))
+}
+
+/// Parses the json output of a scripted reducer into the commands of the
+/// configured storage.
+fn to_record(storage_type: &str, json: serde_json::Value) -> Result<Record, WorkerError> {
+    let record = match storage_type {
+        "None" => Record::None,
+        "Redis" => Record::CRDTCommand(CRDTCommand::from_json_array(&json).or_panic()?),
+        "Postgres" => Record::SQLCommand(serde_json::from_value(json).or_panic()?),
+        _ => return Err(WorkerError::Panic),
+    };
+
+    Ok(record)
+}
+
+/// What the storage gets for a block the reducer had no output for, so that
+/// it still tracks its point and can undo it later.
+fn empty_record(storage_type: &str) -> Record {
+    match storage_type {
+        "Redis" => Record::CRDTCommand(vec![]),
+        "Postgres" => Record::SQLCommand(vec![]),
+        _ => Record::None,
+    }
+}
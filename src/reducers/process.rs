@@ -0,0 +1,221 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use gasket::framework::*;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{ChildStdin, ChildStdout, Command};
+use tracing::{info, warn};
+
+use crate::framework::*;
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// program to spawn, looked up in the PATH
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    /// working directory of the process, defaults to lyra's
+    cwd: Option<String>,
+    /// max time the process may take to reply to a message, it gets restarted
+    /// when exceeded
+    timeout_ms: Option<u64>,
+}
+
+impl Config {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let stage = Stage {
+            command: self.command,
+            args: self.args,
+            cwd: self.cwd,
+            timeout: self.timeout_ms.map(Duration::from_millis),
+            storage_type: ctx.storage_type.clone(),
+            chain: ctx.chain.clone().try_into()?,
+            input: Default::default(),
+            output: Default::default(),
+            ops_count: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
+
+#[derive(Stage)]
+#[stage(name = "reducer-process", unit = "ChainEvent", worker = "Worker")]
+pub struct Stage {
+    command: String,
+    args: Vec<String>,
+    cwd: Option<String>,
+    timeout: Option<Duration>,
+    storage_type: String,
+    chain: ChainValues,
+
+    pub input: ReducerInputPort,
+    pub output: ReducerOutputPort,
+
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+}
+
+/// The running reducer process, killed when dropped.
+struct Child {
+    _process: tokio::process::Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Child {
+    fn spawn(stage: &Stage) -> Result<Self, Error> {
+        let mut command = Command::new(&stage.command);
+
+        command
+            .args(&stage.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            // the process logs through stderr, tracebacks end up next to lyra's
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true);
+
+        if let Some(cwd) = &stage.cwd {
+            command.current_dir(cwd);
+        }
+
+        let mut process = command.spawn().map_err(|e| {
+            Error::reducer(format!(
+                "can't spawn reducer process {}: {}",
+                stage.command, e
+            ))
+        })?;
+
+        let stdin = process.stdin.take().expect("piped stdin");
+        let stdout = process.stdout.take().expect("piped stdout");
+
+        info!("spawned reducer process {}", stage.command);
+
+        Ok(Self {
+            _process: process,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// writes a message line and reads back the reply line
+    async fn exchange(&mut self, message: &str) -> std::io::Result<String> {
+        self.stdin.write_all(message.as_bytes()).await?;
+        self.stdin.write_all(b"\n").await?;
+        self.stdin.flush().await?;
+
+        self.stdout.next_line().await?.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "reducer process closed its stdout",
+            )
+        })
+    }
+}
+
+pub struct Worker {
+    child: Option<Child>,
+}
+
+impl Worker {
+    /// Sends the message to the process, spawning it first if it isn't
+    /// running. A process that fails to reply is dropped, along with its
+    /// pipes, so the next attempt starts from a fresh one.
+    async fn exchange(&mut self, stage: &Stage, message: &str) -> Result<String, Error> {
+        if self.child.is_none() {
+            self.child = Some(Child::spawn(stage)?);
+        }
+
+        let child = self.child.as_mut().expect("spawned child");
+
+        let reply = match stage.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, child.exchange(message))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "reducer process timed out",
+                    ))
+                }),
+            None => child.exchange(message).await,
+        };
+
+        reply.map_err(|e| {
+            self.child = None;
+            Error::reducer(format!("reducer process failed: {}", e))
+        })
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        let child = Child::spawn(stage).or_panic()?;
+
+        Ok(Self { child: Some(child) })
+    }
+
+    async fn schedule(
+        &mut self,
+        stage: &mut Stage,
+    ) -> Result<WorkSchedule<ChainEvent>, WorkerError> {
+        let msg = stage.input.recv().await.or_panic()?;
+        Ok(WorkSchedule::Unit(msg.payload))
+    }
+
+    async fn execute(&mut self, unit: &ChainEvent, stage: &mut Stage) -> Result<(), WorkerError> {
+        let (method, input, context) = match super::to_call(unit, &stage.chain)? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let message = json!({
+            "method": method,
+            "input": input,
+            "context": context,
+        });
+
+        // the unit is retried as a whole, so a crash replays the same event on
+        // the restarted process instead of skipping it
+        let reply = match self.exchange(stage, &message.to_string()).await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("{}", e);
+                return Err(WorkerError::Retry);
+            }
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&reply)
+            .map_err(|e| Error::reducer(format!("invalid reply from reducer process: {}", e)))
+            .or_panic()?;
+
+        // blocks are sent even without output, storage has to track them all
+        let record = match (json.is_null(), unit) {
+            (false, _) => Some(super::to_record(&stage.storage_type, json)?),
+            (true, ChainEvent::Apply(..) | ChainEvent::Undo(..)) => {
+                Some(super::empty_record(&stage.storage_type))
+            }
+            (true, _) => None,
+        };
+
+        if let Some(record) = record {
+            stage
+                .output
+                .send(unit.with_record(record))
+                .await
+                .or_retry()?;
+        }
+
+        stage.ops_count.inc(1);
+
+        match unit {
+            ChainEvent::Apply(point, _) => info!("Processed apply for block {:?}", point),
+            ChainEvent::Undo(point, _) => info!("Processed undo for block {:?}", point),
+            _ => info!("Processed {} for mempool tx", method),
+        }
+
+        Ok(())
+    }
+}
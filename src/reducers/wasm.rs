@@ -0,0 +1,399 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use gasket::framework::*;
+use serde::Deserialize;
+use tracing::info;
+use wasmtime::{
+    Caller, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap, TypedFunc,
+};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+
+use crate::framework::*;
+
+/// `(block_ptr, block_len, context_ptr, context_len) -> (out_ptr << 32) | out_len`
+type ReduceFn = TypedFunc<(u32, u32, u32, u32), u64>;
+
+/// How often the engine epoch moves forward, which is the resolution of
+/// `timeout_ms`
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// What the store hands to host functions
+struct Host {
+    wasi: WasiCtx,
+    limits: StoreLimits,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    reducer_module: String,
+    /// max size of the linear memory of the module, in megabytes
+    max_memory_mb: Option<usize>,
+    /// max wall-clock time a single apply / undo call may take
+    timeout_ms: Option<u64>,
+}
+
+impl Config {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let path = std::fs::canonicalize(&self.reducer_module).map_err(|_| {
+            Error::config(format!("reducer module not found: {}", self.reducer_module))
+        })?;
+
+        if ctx.source_type == "U5C" {
+            return Err(Error::config(
+                "wasm reducer needs blocks as cbor, the U5C source isn't supported",
+            ));
+        }
+
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.epoch_interruption(self.timeout_ms.is_some());
+
+        // compiling up front makes an invalid module fail at startup
+        let engine = Engine::new(&engine_config).map_err(Error::config)?;
+
+        let module = Module::from_file(&engine, &path)
+            .map_err(|e| Error::config(format!("invalid wasm module {}: {}", path.display(), e)))?;
+
+        let stage = Stage {
+            path,
+            engine,
+            module,
+            max_memory_mb: self.max_memory_mb,
+            timeout: self.timeout_ms.map(Duration::from_millis),
+            storage_type: ctx.storage_type.clone(),
+            chain: ctx.chain.clone().try_into()?,
+            input: Default::default(),
+            output: Default::default(),
+            ops_count: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
+
+/// The module only sees blocks as cbor. The U5C source is rejected at
+/// startup, but an archive recorded from it still holds mapped blocks.
+fn to_cbor(record: &Record) -> Result<Option<&[u8]>, WorkerError> {
+    match record {
+        Record::RawBlockPayload(cbor) | Record::EnrichedBlockPayload(cbor, _) => Ok(Some(cbor)),
+        Record::UtxoRpcBlockPayload(_) => Err(Error::reducer(
+            "wasm reducer needs blocks as cbor, got a block mapped to UTxO RPC",
+        ))
+        .or_panic(),
+        _ => Ok(None),
+    }
+}
+
+/// Moves the engine epoch forward on its own thread, so that a call outliving
+/// its deadline traps even when the module is stuck in a loop.
+struct Ticker {
+    stop: Arc<AtomicBool>,
+}
+
+impl Ticker {
+    fn spawn(engine: Engine) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+
+        std::thread::spawn(move || {
+            while !flag.load(Ordering::SeqCst) {
+                std::thread::sleep(EPOCH_TICK);
+                engine.increment_epoch();
+            }
+        });
+
+        Self { stop }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Gives the next call into the module `timeout` before it traps.
+fn arm(store: &mut Store<Host>, timeout: Option<Duration>) {
+    if let Some(timeout) = timeout {
+        let ticks = timeout.as_millis() / EPOCH_TICK.as_millis();
+        store.set_epoch_deadline(ticks.max(1) as u64);
+    }
+}
+
+fn call_error(err: wasmtime::Error, timeout: Option<Duration>) -> Error {
+    match (err.downcast_ref::<Trap>(), timeout) {
+        (Some(Trap::Interrupt), Some(timeout)) => {
+            Error::reducer(format!("timeout of {}ms exceeded", timeout.as_millis()))
+        }
+        _ => Error::reducer(err),
+    }
+}
+
+/// `lyra.log(ptr, len)`: writes the utf-8 string at `ptr` to the lyra log
+fn log(mut caller: Caller<'_, Host>, ptr: u32, len: u32) -> wasmtime::Result<()> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|x| x.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("wasm module doesn't export its memory"))?;
+
+    let (ptr, len) = (ptr as usize, len as usize);
+
+    let bytes = memory
+        .data(&caller)
+        .get(ptr..ptr + len)
+        .ok_or_else(|| wasmtime::Error::msg("log message out of bounds"))?;
+
+    info!("{}", String::from_utf8_lossy(bytes));
+
+    Ok(())
+}
+
+#[derive(Stage)]
+#[stage(name = "reducer-wasm", unit = "ChainEvent", worker = "Worker")]
+pub struct Stage {
+    path: PathBuf,
+    engine: Engine,
+    module: Module,
+    max_memory_mb: Option<usize>,
+    timeout: Option<Duration>,
+    storage_type: String,
+    chain: ChainValues,
+
+    pub input: ReducerInputPort,
+    pub output: ReducerOutputPort,
+
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+}
+
+pub struct Worker {
+    store: Store<Host>,
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    dealloc: Option<TypedFunc<(u32, u32), ()>>,
+    apply: ReduceFn,
+    undo: ReduceFn,
+    timeout: Option<Duration>,
+    _ticker: Option<Ticker>,
+}
+
+impl Worker {
+    /// copies `bytes` into a buffer allocated by the module
+    fn write(&mut self, bytes: &[u8]) -> Result<(u32, u32), Error> {
+        let len = bytes.len() as u32;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(Error::reducer)?;
+
+        self.memory
+            .write(&mut self.store, ptr as usize, bytes)
+            .map_err(Error::reducer)?;
+
+        Ok((ptr, len))
+    }
+
+    fn free(&mut self, ptr: u32, len: u32) -> Result<(), Error> {
+        match &self.dealloc {
+            Some(dealloc) => dealloc
+                .call(&mut self.store, (ptr, len))
+                .map_err(Error::reducer),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `apply` or `undo` over a block, returning the json it wrote back,
+    /// if any.
+    fn reduce(
+        &mut self,
+        rollback: bool,
+        block: &[u8],
+        context: &serde_json::Value,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        let context = context.to_string();
+
+        // the deadline covers the buffer allocations too
+        arm(&mut self.store, self.timeout);
+
+        let (block_ptr, block_len) = self.write(block)?;
+        let (context_ptr, context_len) = self.write(context.as_bytes())?;
+
+        let func = if rollback { &self.undo } else { &self.apply };
+
+        let packed = func
+            .call(
+                &mut self.store,
+                (block_ptr, block_len, context_ptr, context_len),
+            )
+            .map_err(|e| call_error(e, self.timeout))?;
+
+        let (out_ptr, out_len) = ((packed >> 32) as u32, packed as u32);
+
+        let output = match out_len {
+            0 => None,
+            _ => {
+                let (start, end) = (out_ptr as usize, out_ptr as usize + out_len as usize);
+
+                let bytes = self
+                    .memory
+                    .data(&self.store)
+                    .get(start..end)
+                    .ok_or_else(|| Error::reducer("reducer output out of bounds"))?;
+
+                Some(serde_json::from_slice(bytes).map_err(Error::parsing)?)
+            }
+        };
+
+        self.free(block_ptr, block_len)?;
+        self.free(context_ptr, context_len)?;
+
+        if out_len > 0 {
+            self.free(out_ptr, out_len)?;
+        }
+
+        Ok(output.filter(|x: &serde_json::Value| !x.is_null()))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        let limits = match stage.max_memory_mb {
+            Some(mb) => StoreLimitsBuilder::new().memory_size(mb * 1024 * 1024),
+            None => StoreLimitsBuilder::new(),
+        };
+
+        // no preopened dirs nor env, the module only gets stdio
+        let wasi = WasiCtxBuilder::new()
+            .inherit_stdout()
+            .inherit_stderr()
+            .build();
+
+        let mut store = Store::new(
+            &stage.engine,
+            Host {
+                wasi,
+                limits: limits.build(),
+            },
+        );
+
+        store.limiter(|host| &mut host.limits);
+
+        let ticker = stage.timeout.map(|_| Ticker::spawn(stage.engine.clone()));
+
+        let mut linker = Linker::new(&stage.engine);
+
+        wasmtime_wasi::add_to_linker(&mut linker, |host: &mut Host| &mut host.wasi)
+            .map_err(Error::reducer)
+            .or_panic()?;
+
+        linker
+            .func_wrap("lyra", "log", log)
+            .map_err(Error::reducer)
+            .or_panic()?;
+
+        // the start function of the module, if any, runs on instantiation
+        arm(&mut store, stage.timeout);
+
+        let instance = linker
+            .instantiate(&mut store, &stage.module)
+            .map_err(|e| {
+                Error::reducer(format!(
+                    "can't instantiate reducer module {}: {}",
+                    stage.path.display(),
+                    e
+                ))
+            })
+            .or_panic()?;
+
+        // wasi reactors expect this to run before any other export
+        if let Ok(init) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+            arm(&mut store, stage.timeout);
+            init.call(&mut store, ())
+                .map_err(|e| call_error(e, stage.timeout))
+                .or_panic()?;
+        }
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| Error::reducer("reducer module must export its memory"))
+            .or_panic()?;
+
+        let alloc = instance
+            .get_typed_func(&mut store, "alloc")
+            .map_err(Error::reducer)
+            .or_panic()?;
+
+        let dealloc = instance.get_typed_func(&mut store, "dealloc").ok();
+
+        let apply = instance
+            .get_typed_func(&mut store, "apply")
+            .map_err(Error::reducer)
+            .or_panic()?;
+
+        let undo = instance
+            .get_typed_func(&mut store, "undo")
+            .map_err(Error::reducer)
+            .or_panic()?;
+
+        Ok(Self {
+            store,
+            memory,
+            alloc,
+            dealloc,
+            apply,
+            undo,
+            timeout: stage.timeout,
+            _ticker: ticker,
+        })
+    }
+
+    async fn schedule(
+        &mut self,
+        stage: &mut Stage,
+    ) -> Result<WorkSchedule<ChainEvent>, WorkerError> {
+        let msg = stage.input.recv().await.or_panic()?;
+        Ok(WorkSchedule::Unit(msg.payload))
+    }
+
+    async fn execute(&mut self, unit: &ChainEvent, stage: &mut Stage) -> Result<(), WorkerError> {
+        let (point, record) = match unit {
+            ChainEvent::Apply(point, record) | ChainEvent::Undo(point, record) => (point, record),
+            // the module only reduces blocks, mempool events and resets are skipped
+            _ => return Ok(()),
+        };
+
+        let block = match to_cbor(record)? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let rollback = matches!(unit, ChainEvent::Undo(..));
+        let context = super::block_context(point, rollback, &stage.chain);
+
+        let output = self.reduce(rollback, block, &context).or_panic()?;
+
+        // blocks are sent even without output, storage has to track them all
+        let record = match output {
+            Some(json) => super::to_record(&stage.storage_type, json)?,
+            None => super::empty_record(&stage.storage_type),
+        };
+
+        stage
+            .output
+            .send(unit.with_record(record))
+            .await
+            .or_retry()?;
+
+        stage.ops_count.inc(1);
+
+        match unit {
+            ChainEvent::Apply(..) => info!("Processed apply for block {:?}", point),
+            _ => info!("Processed undo for block {:?}", point),
+        }
+
+        Ok(())
+    }
+}
@@ -1,126 +1,491 @@
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use gasket::framework::*;
+use pallas::ledger::traverse::{Era, MultiEraBlock};
 use pallas::network::miniprotocols::Point;
-use serde::Deserialize;
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use utxorpc_spec::utxorpc::v1alpha::cardano as u5c;
 
+use crate::framework::model::BlockContext;
 use crate::framework::*;
 
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+pub enum Payload {
+    /// the block cbor, along with the outputs it consumes that are known
+    Cbor(Vec<u8>, BlockContext),
+    Mapped(Box<u5c::Block>),
+}
+
 pub enum Action {
-    Apply(Vec<u8>),
-    Undo(Vec<u8>),
+    Apply(Payload),
+    Undo(Payload),
+    Reset(Point),
+}
+
+/// How blocks are encoded inside each file of the archive. Any file can
+/// additionally be gzip or zstd compressed, which is detected automatically.
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum Format {
+    /// a single hex-encoded block per file
+    #[default]
+    Hex,
+    /// a single binary cbor block per file
+    Raw,
+    /// one hex-encoded block per line
+    HexLines,
+    /// one [`ArchiveEntry`] json object per line
+    JsonLines,
+}
+
+/// The block carried by an [`ArchiveEntry`].
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchivePayload {
+    /// hex-encoded block cbor
+    Cbor(String),
+    /// an already mapped UTxO RPC block, for upstream sources that don't
+    /// provide the original cbor
+    Block(Box<u5c::Block>),
+}
+
+/// An output consumed by an archived block, as the source resolved it.
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveUtxo {
+    era: u16,
+    /// hex-encoded output cbor
+    cbor: String,
+}
+
+/// The outputs consumed by an archived block, by `<tx hash>#<index>`.
+pub type ArchiveContext = BTreeMap<String, ArchiveUtxo>;
+
+/// An entry of a `JsonLines` archive. Unlike the other formats, each entry
+/// carries its own action, so rollbacks can be replayed exactly.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ArchiveEntry {
+    Apply {
+        #[serde(flatten)]
+        payload: ArchivePayload,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        consumed: ArchiveContext,
+    },
+    Undo {
+        #[serde(flatten)]
+        payload: ArchivePayload,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        consumed: ArchiveContext,
+    },
+    Reset {
+        slot: u64,
+        hash: Option<String>,
+    },
+}
+
+/// An entry of the optional manifest file, which lists the actions to
+/// perform explicitly instead of inferring them from the directory listing.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ManifestEntry {
+    Apply { file: String },
+    Undo { file: String },
+    Reset { slot: u64, hash: Option<String> },
+}
+
+enum Entry {
+    File { path: PathBuf, undo: bool },
+    Reset(Point),
+}
+
+fn to_point(slot: u64, hash: Option<String>) -> Result<Point, Error> {
+    match (slot, hash) {
+        (slot, Some(hash)) => Ok(Point::Specific(
+            slot,
+            hex::decode(hash).map_err(Error::parsing)?,
+        )),
+        (0, None) => Ok(Point::Origin),
+        (slot, None) => Err(Error::parsing(format!(
+            "reset to slot {} is missing the block hash",
+            slot
+        ))),
+    }
+}
+
+/// Exports the outputs a block context resolved, to archive them along with
+/// the block.
+pub fn to_archive_context(ctx: &BlockContext) -> ArchiveContext {
+    ctx.utxos()
+        .map(|(key, era, cbor)| {
+            let utxo = ArchiveUtxo {
+                era: era.into(),
+                cbor: hex::encode(cbor),
+            };
+
+            (key.clone(), utxo)
+        })
+        .collect()
 }
 
-#[derive(Clone)]
-struct LedgerStore;
+fn from_archive_context(consumed: ArchiveContext) -> Result<BlockContext, Error> {
+    let mut ctx = BlockContext::default();
 
-impl LedgerStore {
-    fn new() -> Self {
-        Self
+    for (key, utxo) in consumed {
+        let era = Era::try_from(utxo.era).map_err(Error::parsing)?;
+        let cbor = hex::decode(utxo.cbor).map_err(Error::parsing)?;
+        ctx.import_utxo(key, era, cbor);
     }
+
+    Ok(ctx)
 }
 
-impl pallas::interop::utxorpc::LedgerContext for LedgerStore {
-    fn get_utxos<'a>(
-        &self,
-        _refs: &[pallas::interop::utxorpc::TxoRef],
-    ) -> Option<pallas::interop::utxorpc::UtxoMap> {
-        None
+impl ArchivePayload {
+    fn into_payload(self, consumed: ArchiveContext) -> Result<Payload, Error> {
+        match self {
+            ArchivePayload::Cbor(x) => Ok(Payload::Cbor(
+                hex::decode(x).map_err(Error::parsing)?,
+                from_archive_context(consumed)?,
+            )),
+            // mapped blocks already carry the outputs their inputs consume
+            ArchivePayload::Block(x) => Ok(Payload::Mapped(x)),
+        }
+    }
+}
+
+impl ArchiveEntry {
+    fn into_action(self) -> Result<Action, Error> {
+        match self {
+            ArchiveEntry::Apply { payload, consumed } => {
+                Ok(Action::Apply(payload.into_payload(consumed)?))
+            }
+            ArchiveEntry::Undo { payload, consumed } => {
+                Ok(Action::Undo(payload.into_payload(consumed)?))
+            }
+            ArchiveEntry::Reset { slot, hash } => Ok(Action::Reset(to_point(slot, hash)?)),
+        }
+    }
+}
+
+fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if bytes.starts_with(GZIP_MAGIC) {
+        let mut out = Vec::new();
+        flate2::read::MultiGzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut out)
+            .map_err(Error::parsing)?;
+        return Ok(out);
+    }
+
+    if bytes.starts_with(ZSTD_MAGIC) {
+        return zstd::stream::decode_all(bytes.as_slice()).map_err(Error::parsing);
+    }
+
+    Ok(bytes)
+}
+
+fn parse_file(bytes: Vec<u8>, format: Format, undo: bool) -> Result<Vec<Action>, Error> {
+    let bytes = decompress(bytes)?;
+
+    let to_action = |cbor: Vec<u8>| {
+        if undo {
+            Action::Undo(Payload::Cbor(cbor, BlockContext::default()))
+        } else {
+            Action::Apply(Payload::Cbor(cbor, BlockContext::default()))
+        }
+    };
+
+    match format {
+        Format::Raw => Ok(vec![to_action(bytes)]),
+        Format::Hex => {
+            let text = std::str::from_utf8(&bytes).map_err(Error::parsing)?;
+            let cbor = hex::decode(text.trim()).map_err(Error::parsing)?;
+            Ok(vec![to_action(cbor)])
+        }
+        Format::HexLines => {
+            let text = std::str::from_utf8(&bytes).map_err(Error::parsing)?;
+
+            text.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(|line| hex::decode(line).map(to_action).map_err(Error::parsing))
+                .collect()
+        }
+        Format::JsonLines => {
+            let text = std::str::from_utf8(&bytes).map_err(Error::parsing)?;
+
+            text.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(|line| {
+                    serde_json::from_str::<ArchiveEntry>(line)
+                        .map_err(Error::parsing)?
+                        .into_action()
+                })
+                .collect()
+        }
+    }
+}
+
+/// The point of a mapped block, which only its header carries.
+fn mapped_point(block: &u5c::Block) -> Result<Point, WorkerError> {
+    match &block.header {
+        Some(header) => Ok(Point::Specific(header.slot, header.hash.to_vec())),
+        None => {
+            error!("archive block has no header, can't tell its point");
+            Err(WorkerError::Panic)
+        }
+    }
+}
+
+fn block_point(payload: &Payload) -> Result<Option<Point>, Error> {
+    match payload {
+        Payload::Cbor(cbor, _) => {
+            let block = MultiEraBlock::decode(cbor).map_err(Error::cbor)?;
+            Ok(Some(Point::Specific(block.slot(), block.hash().to_vec())))
+        }
+        Payload::Mapped(block) => Ok(block
+            .header
+            .as_ref()
+            .map(|header| Point::Specific(header.slot, header.hash.to_vec()))),
     }
 }
 
 pub struct Worker {
     index: usize,
-    files: Vec<PathBuf>,
-    mapper: pallas::interop::utxorpc::Mapper<LedgerStore>,
+    entries: Vec<Entry>,
+    seen: HashSet<PathBuf>,
+    manifest_lines: usize,
+    resume_from: Option<Point>,
 }
 
-#[async_trait::async_trait(?Send)]
-impl gasket::framework::Worker<Stage> for Worker {
-    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+impl Worker {
+    /// Picks up entries that are not yet known, either new files in the
+    /// directory or new lines appended to the manifest.
+    async fn scan(&mut self, config: &Config) -> Result<(), WorkerError> {
+        let dir = Path::new(&config.dir);
+
+        if let Some(manifest) = &config.manifest {
+            let text = tokio::fs::read_to_string(manifest).await.or_retry()?;
+
+            let lines: Vec<_> = text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect();
+
+            for line in lines.iter().skip(self.manifest_lines) {
+                let entry = match serde_json::from_str::<ManifestEntry>(line)
+                    .map_err(Error::parsing)
+                    .or_panic()?
+                {
+                    ManifestEntry::Apply { file } => Entry::File {
+                        path: dir.join(file),
+                        undo: false,
+                    },
+                    ManifestEntry::Undo { file } => Entry::File {
+                        path: dir.join(file),
+                        undo: true,
+                    },
+                    ManifestEntry::Reset { slot, hash } => {
+                        Entry::Reset(to_point(slot, hash).or_panic()?)
+                    }
+                };
+
+                self.entries.push(entry);
+            }
+
+            self.manifest_lines = lines.len();
+
+            return Ok(());
+        }
+
         let mut files = Vec::new();
-        let mut dir = tokio::fs::read_dir(&stage.config.dir).await.unwrap();
+        let mut listing = tokio::fs::read_dir(dir).await.or_retry()?;
 
-        while let Some(entry) = dir.next_entry().await.map_err(|_| WorkerError::Panic)? {
+        while let Some(entry) = listing.next_entry().await.or_retry()? {
             let path = entry.path();
-            if path.is_file() {
+            if path.is_file() && !self.seen.contains(&path) {
                 files.push(path);
             }
         }
 
         files.sort();
 
-        let ledger = LedgerStore::new();
-        let mapper = pallas::interop::utxorpc::Mapper::new(ledger);
+        for path in files {
+            let undo = path
+                .file_name()
+                .and_then(|os_str| os_str.to_str())
+                .is_some_and(|name| name.contains("undo"));
 
-        Ok(Self {
-            index: 0,
-            files,
-            mapper,
-        })
+            self.seen.insert(path.clone());
+            self.entries.push(Entry::File { path, undo });
+        }
+
+        Ok(())
     }
 
-    async fn schedule(&mut self, _: &mut Stage) -> Result<WorkSchedule<Vec<Action>>, WorkerError> {
-        if self.index < self.files.len() {
-            let path = &self.files[self.index];
-            self.index += 1;
+    /// The point and record of an archived block, None for a mapped block
+    /// without a body. Blocks archived as cbor go through as cbor, so that
+    /// reducers that only take cbor can replay them too.
+    fn to_record(&self, payload: &Payload) -> Result<Option<(Point, Record)>, WorkerError> {
+        match payload {
+            Payload::Cbor(cbor, ctx) => {
+                let block = MultiEraBlock::decode(cbor)
+                    .map_err(Error::cbor)
+                    .or_panic()?;
+                let point = Point::Specific(block.slot(), block.hash().to_vec());
+                let record = Record::EnrichedBlockPayload(cbor.clone(), ctx.clone());
+                Ok(Some((point, record)))
+            }
+            Payload::Mapped(block) if block.body.is_some() => {
+                let point = mapped_point(block)?;
+                let record = Record::UtxoRpcBlockPayload(block.as_ref().clone());
+                Ok(Some((point, record)))
+            }
+            Payload::Mapped(_) => Ok(None),
+        }
+    }
 
-            let cbor = tokio::fs::read(&path)
-                .await
-                .map_err(|_| WorkerError::Panic)?;
+    /// Drops every action up to (and including) the apply of the point the
+    /// storage cursor is at, so that a restart doesn't replay the archive
+    /// from the beginning. If the archive doesn't have that exact block, it
+    /// resumes at the first block past its slot.
+    fn skip_until_cursor(&mut self, actions: Vec<Action>) -> Result<Vec<Action>, WorkerError> {
+        let target = match &self.resume_from {
+            Some(x) => x.clone(),
+            None => return Ok(actions),
+        };
 
-            let file_name = path
-                .file_name()
-                .and_then(|os_str| os_str.to_str())
-                .unwrap_or("");
+        let mut actions = actions.into_iter().peekable();
 
-            let action = if file_name.contains("undo") {
-                Action::Undo(hex::decode(cbor).unwrap())
-            } else {
-                Action::Apply(hex::decode(cbor).unwrap())
+        while let Some(action) = actions.peek() {
+            let point = match action {
+                Action::Apply(payload) => block_point(payload).or_panic()?,
+                _ => None,
             };
 
-            Ok(WorkSchedule::Unit(vec![action]))
-        } else {
-            Ok(WorkSchedule::Idle)
+            match point {
+                Some(point) if point == target => {
+                    info!("resuming archive after cursor {:?}", target);
+                    self.resume_from = None;
+                    actions.next();
+                    break;
+                }
+                Some(point) if point.slot_or_default() > target.slot_or_default() => {
+                    warn!(
+                        "cursor {:?} not found in archive, resuming at {:?}",
+                        target, point
+                    );
+                    self.resume_from = None;
+                    break;
+                }
+                _ => {
+                    actions.next();
+                }
+            }
+        }
+
+        Ok(actions.collect())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        let mut worker = Self {
+            index: 0,
+            entries: Vec::new(),
+            seen: HashSet::new(),
+            manifest_lines: 0,
+            resume_from: stage.resume_from.clone(),
+        };
+
+        worker.scan(&stage.config).await?;
+
+        Ok(worker)
+    }
+
+    async fn schedule(
+        &mut self,
+        stage: &mut Stage,
+    ) -> Result<WorkSchedule<Vec<Action>>, WorkerError> {
+        if self.index >= self.entries.len() && stage.config.watch.unwrap_or(false) {
+            let interval = stage.config.watch_interval_ms.unwrap_or(1000);
+            tokio::time::sleep(Duration::from_millis(interval)).await;
+            self.scan(&stage.config).await?;
+        }
+
+        if self.index >= self.entries.len() {
+            if let Some(point) = &self.resume_from {
+                warn!("archive exhausted before reaching cursor {:?}", point);
+            }
+
+            return Ok(WorkSchedule::Idle);
         }
+
+        let actions = match &self.entries[self.index] {
+            Entry::File { path, undo } => {
+                let bytes = tokio::fs::read(path).await.or_retry()?;
+
+                parse_file(bytes, stage.config.format.unwrap_or_default(), *undo)
+                    .map_err(|err| Error::source(format!("{}: {}", path.display(), err)))
+                    .or_panic()?
+            }
+            Entry::Reset(point) => vec![Action::Reset(point.clone())],
+        };
+
+        self.index += 1;
+
+        let actions = self.skip_until_cursor(actions)?;
+
+        Ok(WorkSchedule::Unit(actions))
     }
 
     async fn execute(&mut self, unit: &Vec<Action>, stage: &mut Stage) -> Result<(), WorkerError> {
         for action in unit {
             match action {
-                Action::Apply(cbor) => {
-                    let block = self.mapper.map_block_cbor(&cbor);
-                    if block.body.is_some() {
-                        let header = block.header.as_ref().unwrap();
-                        let event = ChainEvent::Apply(
-                            Point::Specific(header.slot, header.hash.to_vec()),
-                            Record::UtxoRpcBlockPayload(block.clone()),
-                        );
+                Action::Apply(payload) => {
+                    if let Some((point, record)) = self.to_record(payload)? {
+                        let slot = point.slot_or_default();
 
-                        info!("Applying block {:?}", event.point());
+                        info!("Applying block {:?}", point);
+
+                        let event = ChainEvent::Apply(point, record);
 
                         stage.output.send(event.into()).await.or_panic()?;
-                        stage.chain_tip.set(header.slot as i64);
+                        stage.chain_tip.set(slot as i64);
                     }
                 }
-                Action::Undo(cbor) => {
-                    let block = self.mapper.map_block_cbor(&cbor);
-                    if block.body.is_some() {
-                        let header = block.header.as_ref().unwrap();
-                        let event = ChainEvent::Undo(
-                            Point::Specific(header.slot, header.hash.to_vec()),
-                            Record::UtxoRpcBlockPayload(block.clone()),
-                        );
+                Action::Undo(payload) => {
+                    if let Some((point, record)) = self.to_record(payload)? {
+                        let slot = point.slot_or_default();
+
+                        info!("Undoing block {:?}", point);
 
-                        info!("Undoing block {:?}", event.point());
+                        let event = ChainEvent::Undo(point, record);
 
                         stage.output.send(event.into()).await.or_panic()?;
-                        stage.chain_tip.set(header.slot as i64);
+                        stage.chain_tip.set(slot as i64);
                     }
                 }
+                Action::Reset(point) => {
+                    info!("Resetting to {:?}", point);
+
+                    stage
+                        .output
+                        .send(ChainEvent::reset(point.clone()))
+                        .await
+                        .or_panic()?;
+
+                    stage.chain_tip.set(point.slot_or_default() as i64);
+                }
             }
+
+            stage.ops_count.inc(1);
         }
 
         Ok(())
@@ -131,6 +496,7 @@ impl gasket::framework::Worker<Stage> for Worker {
 #[stage(name = "source-cbor", unit = "Vec<Action>", worker = "Worker")]
 pub struct Stage {
     config: Config,
+    resume_from: Option<Point>,
     pub output: SourceOutputPort,
     #[metric]
     ops_count: gasket::metrics::Counter,
@@ -141,12 +507,28 @@ pub struct Stage {
 #[derive(Deserialize)]
 pub struct Config {
     dir: String,
+    format: Option<Format>,
+    manifest: Option<String>,
+    watch: Option<bool>,
+    watch_interval_ms: Option<u64>,
 }
 
 impl Config {
-    pub fn bootstrapper(self, _ctx: &Context) -> Result<Stage, Error> {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        // without a cursor (eg: a fresh storage or a hot reload rewind) an
+        // intersect at a specific point tells where to resume from, any other
+        // replays the whole archive
+        let resume_from = match (ctx.cursor.latest_known_point(), &ctx.intersect) {
+            (Some(x), _) => Some(x),
+            (None, IntersectConfig::Point(..)) => {
+                ctx.intersect.points().and_then(|x| x.into_iter().next())
+            }
+            (None, _) => None,
+        };
+
         let stage = Stage {
             config: self,
+            resume_from,
             output: Default::default(),
             ops_count: Default::default(),
             chain_tip: Default::default(),
@@ -54,4 +54,13 @@ impl Config {
             Config::U5C(c) => Ok(Bootstrapper::U5C(c.bootstrapper(ctx)?)),
         }
     }
+
+    pub fn get_type(&self) -> &'static str {
+        match self {
+            Config::CBOR(_) => "CBOR",
+            Config::N2N(_) => "N2N",
+            Config::N2C(_) => "N2C",
+            Config::U5C(_) => "U5C",
+        }
+    }
 }
@@ -1,22 +1,28 @@
 use gasket::framework::*;
 use pallas::ledger::traverse::wellknown::GenesisValues;
-use pallas::ledger::traverse::MultiEraBlock;
+use pallas::ledger::traverse::{Era, MultiEraBlock, MultiEraTx};
 use pallas::network::facades::NodeClient;
 use pallas::network::miniprotocols::chainsync::BlockContent;
 use pallas::network::miniprotocols::chainsync::NextResponse;
+use pallas::network::miniprotocols::localtxsubmission::EraTx;
 use pallas::network::miniprotocols::Point;
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::PathBuf;
+use std::time::Duration;
 use tracing::{debug, info};
 
 use crate::framework::*;
 
+const MAX_DEPARTED_TXS: usize = 1000;
+
+pub enum WorkUnit {
+    ChainSync(NextResponse<BlockContent>),
+    Mempool(Vec<EraTx>),
+}
+
 #[derive(Stage)]
-#[stage(
-    name = "source-n2c",
-    unit = "NextResponse<BlockContent>",
-    worker = "Worker"
-)]
+#[stage(name = "source-n2c", unit = "WorkUnit", worker = "Worker")]
 pub struct Stage {
     config: Config,
 
@@ -24,6 +30,9 @@ pub struct Stage {
 
     intersect: IntersectConfig,
 
+    /// starts as the cursor persisted by the storage and then tracks every
+    /// point emitted downstream, so that a restarted worker re-intersects
+    /// exactly where the previous one left off.
     cursor: Breadcrumbs,
 
     pub output: SourceOutputPort,
@@ -39,6 +48,9 @@ pub struct Stage {
 
     #[metric]
     rollback_count: gasket::metrics::Counter,
+
+    #[metric]
+    mempool_size: gasket::metrics::Gauge,
 }
 
 async fn intersect_from_config(
@@ -84,11 +96,174 @@ async fn intersect_from_breadcrumbs(
     Ok(())
 }
 
+struct Mempool {
+    session: NodeClient,
+    poll_interval: Duration,
+    pending: HashSet<String>,
+    // txs that left the mempool recently, in case their block shows up after
+    // we noticed them gone
+    departed: VecDeque<String>,
+}
+
+impl Mempool {
+    async fn connect(stage: &Stage, config: &MempoolConfig) -> Result<Self, WorkerError> {
+        let session = NodeClient::connect(&stage.config.socket_path, stage.chain.magic)
+            .await
+            .or_retry()?;
+
+        Ok(Self {
+            session,
+            poll_interval: Duration::from_millis(config.poll_interval_ms.unwrap_or(1000)),
+            pending: Default::default(),
+            departed: Default::default(),
+        })
+    }
+
+    async fn snapshot(&mut self) -> Result<Vec<EraTx>, WorkerError> {
+        let monitor = self.session.monitor();
+
+        monitor.acquire().await.or_restart()?;
+
+        let mut txs = Vec::new();
+
+        while let Some(tx) = monitor.query_next_tx().await.or_restart()? {
+            txs.push(tx);
+        }
+
+        monitor.release().await.or_restart()?;
+
+        Ok(txs)
+    }
+
+    fn depart(&mut self, hash: String) {
+        self.departed.push_back(hash);
+
+        if self.departed.len() > MAX_DEPARTED_TXS {
+            self.departed.pop_front();
+        }
+    }
+
+    fn confirm(&mut self, hash: &str) -> bool {
+        if self.pending.remove(hash) {
+            return true;
+        }
+
+        match self.departed.iter().position(|x| x == hash) {
+            Some(idx) => {
+                self.departed.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn decode_tx_hash(era: u16, cbor: &[u8]) -> Result<String, WorkerError> {
+    let era = Era::try_from(era).or_panic()?;
+    let tx = MultiEraTx::decode_for_era(era, cbor).or_panic()?;
+    Ok(tx.hash().to_string())
+}
+
 pub struct Worker {
     peer_session: NodeClient,
+    mempool: Option<Mempool>,
 }
 
 impl Worker {
+    async fn process_mempool(
+        &mut self,
+        stage: &mut Stage,
+        txs: &[EraTx],
+    ) -> Result<(), WorkerError> {
+        let mempool = match &mut self.mempool {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let mut current = HashMap::new();
+
+        for EraTx(era, cbor) in txs {
+            current.insert(decode_tx_hash(*era, cbor)?, (*era, cbor));
+        }
+
+        let departed: Vec<_> = mempool
+            .pending
+            .iter()
+            .filter(|hash| !current.contains_key(*hash))
+            .cloned()
+            .collect();
+
+        for hash in departed {
+            debug!(%hash, "tx left mempool");
+
+            mempool.pending.remove(&hash);
+
+            stage
+                .output
+                .send(ChainEvent::mempool_remove(hash.clone(), Record::None))
+                .await
+                .or_panic()?;
+
+            mempool.depart(hash);
+            stage.ops_count.inc(1);
+        }
+
+        for (hash, (era, cbor)) in current {
+            if !mempool.pending.insert(hash.clone()) {
+                continue;
+            }
+
+            debug!(%hash, "tx entered mempool");
+
+            let record = Record::RawTxPayload(era, cbor.clone());
+
+            stage
+                .output
+                .send(ChainEvent::mempool_add(hash, record))
+                .await
+                .or_panic()?;
+
+            stage.ops_count.inc(1);
+        }
+
+        stage.mempool_size.set(mempool.pending.len() as i64);
+
+        Ok(())
+    }
+
+    async fn confirm_mempool_txs(
+        &mut self,
+        stage: &mut Stage,
+        block: &MultiEraBlock<'_>,
+    ) -> Result<(), WorkerError> {
+        let mempool = match &mut self.mempool {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        for tx in block.txs() {
+            let hash = tx.hash().to_string();
+
+            if !mempool.confirm(&hash) {
+                continue;
+            }
+
+            debug!(%hash, "mempool tx confirmed");
+
+            stage
+                .output
+                .send(ChainEvent::mempool_confirm(hash, Record::None))
+                .await
+                .or_panic()?;
+
+            stage.ops_count.inc(1);
+        }
+
+        stage.mempool_size.set(mempool.pending.len() as i64);
+
+        Ok(())
+    }
+
     async fn process_next(
         &mut self,
         stage: &mut Stage,
@@ -106,11 +281,14 @@ impl Worker {
                 let evt = ChainEvent::Apply(point.clone(), Record::RawBlockPayload(cbor.to_vec()));
 
                 stage.output.send(evt.into()).await.or_panic()?;
+                stage.cursor.track(point);
 
                 stage.chain_tip.set(tip.0.slot_or_default() as i64);
                 stage.current_slot.set(slot as i64);
                 stage.ops_count.inc(1);
 
+                self.confirm_mempool_txs(stage, &block).await?;
+
                 Ok(())
             }
             NextResponse::RollBackward(point, tip) => {
@@ -124,6 +302,7 @@ impl Worker {
                     .send(ChainEvent::reset(point.clone()))
                     .await
                     .or_panic()?;
+                stage.cursor.track(point.clone());
 
                 stage.chain_tip.set(tip.0.slot_or_default() as i64);
                 stage.current_slot.set(point.slot_or_default() as i64);
@@ -155,7 +334,15 @@ impl gasket::framework::Worker<Stage> for Worker {
             intersect_from_breadcrumbs(&mut peer_session, &stage.cursor).await?;
         }
 
-        let worker = Self { peer_session };
+        let mempool = match &stage.config.mempool {
+            Some(config) => Some(Mempool::connect(stage, config).await?),
+            None => None,
+        };
+
+        let worker = Self {
+            peer_session,
+            mempool,
+        };
 
         Ok(worker)
     }
@@ -163,42 +350,62 @@ impl gasket::framework::Worker<Stage> for Worker {
     async fn schedule(
         &mut self,
         _stage: &mut Stage,
-    ) -> Result<WorkSchedule<NextResponse<BlockContent>>, WorkerError> {
+    ) -> Result<WorkSchedule<WorkUnit>, WorkerError> {
         let client = self.peer_session.chainsync();
 
-        let next = match client.has_agency() {
-            true => {
-                info!("requesting next block");
-                client.request_next().await.or_restart()?
-            }
-            false => {
+        if client.has_agency() {
+            info!("requesting next block");
+            let next = client.request_next().await.or_restart()?;
+            return Ok(WorkSchedule::Unit(WorkUnit::ChainSync(next)));
+        }
+
+        // we only poll the mempool while waiting at the tip, there's nothing
+        // meaningful pending while we're still catching up
+        let mempool = match &mut self.mempool {
+            Some(x) => x,
+            None => {
                 info!("awaiting next block (blocking)");
-                client.recv_while_must_reply().await.or_restart()?
+                let next = client.recv_while_must_reply().await.or_restart()?;
+                return Ok(WorkSchedule::Unit(WorkUnit::ChainSync(next)));
             }
         };
 
-        Ok(WorkSchedule::Unit(next))
+        tokio::select! {
+            next = client.recv_while_must_reply() => {
+                let next = next.or_restart()?;
+                Ok(WorkSchedule::Unit(WorkUnit::ChainSync(next)))
+            }
+            _ = tokio::time::sleep(mempool.poll_interval) => {
+                let txs = mempool.snapshot().await?;
+                Ok(WorkSchedule::Unit(WorkUnit::Mempool(txs)))
+            }
+        }
     }
 
-    async fn execute(
-        &mut self,
-        unit: &NextResponse<BlockContent>,
-        stage: &mut Stage,
-    ) -> Result<(), WorkerError> {
-        self.process_next(stage, unit).await
+    async fn execute(&mut self, unit: &WorkUnit, stage: &mut Stage) -> Result<(), WorkerError> {
+        match unit {
+            WorkUnit::ChainSync(next) => self.process_next(stage, next).await,
+            WorkUnit::Mempool(txs) => self.process_mempool(stage, txs).await,
+        }
     }
 }
 
+#[derive(Deserialize)]
+pub struct MempoolConfig {
+    poll_interval_ms: Option<u64>,
+}
+
 #[derive(Deserialize)]
 pub struct Config {
     socket_path: PathBuf,
+    mempool: Option<MempoolConfig>,
 }
 
 impl Config {
     pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
         let stage = Stage {
             config: self,
-            chain: ctx.chain.clone().into(),
+            chain: ctx.chain.clone().try_into()?,
             intersect: ctx.intersect.clone(),
             cursor: ctx.cursor.clone(),
             output: Default::default(),
@@ -206,6 +413,7 @@ impl Config {
             chain_tip: Default::default(),
             current_slot: Default::default(),
             rollback_count: Default::default(),
+            mempool_size: Default::default(),
         };
 
         Ok(stage)
@@ -24,6 +24,9 @@ pub struct Stage {
 
     intersect: IntersectConfig,
 
+    /// starts as the cursor persisted by the storage and then tracks every
+    /// point emitted downstream, so that a restarted worker re-intersects
+    /// exactly where the previous one left off.
     cursor: Breadcrumbs,
 
     pub output: SourceOutputPort,
@@ -125,6 +128,7 @@ impl Worker {
                 );
 
                 stage.output.send(evt.into()).await.or_panic()?;
+                stage.cursor.track(point);
 
                 stage.chain_tip.set(tip.0.slot_or_default() as i64);
                 stage.current_slot.set(slot as i64);
@@ -143,6 +147,7 @@ impl Worker {
                     .send(ChainEvent::reset(point.clone()))
                     .await
                     .or_panic()?;
+                stage.cursor.track(point.clone());
 
                 stage.chain_tip.set(tip.0.slot_or_default() as i64);
                 stage.current_slot.set(point.slot_or_default() as i64);
@@ -225,7 +230,7 @@ impl Config {
     pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
         let stage = Stage {
             config: self,
-            chain: ctx.chain.clone().into(),
+            chain: ctx.chain.clone().try_into()?,
             intersect: ctx.intersect.clone(),
             cursor: ctx.cursor.clone(),
             output: Default::default(),
@@ -26,7 +26,13 @@ impl gasket::framework::Worker<Stage> for Worker {
     }
 
     async fn execute(&mut self, unit: &ChainEvent, stage: &mut Stage) -> Result<(), WorkerError> {
-        let point = unit.point().clone();
+        let point = match unit.point() {
+            Some(point) => point.clone(),
+            None => {
+                stage.ops_count.inc(1);
+                return Ok(());
+            }
+        };
 
         info!("Stored block {:?}", point);
 
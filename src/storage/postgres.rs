@@ -13,6 +13,48 @@ pub struct Worker {
     pool: Pool<PostgresConnectionManager<NoTls>>,
 }
 
+impl Worker {
+    // mempool commands run in their own transaction, but they don't move the
+    // cursor since they don't belong to any block
+    async fn execute_mempool(
+        &mut self,
+        record: &Record,
+        stage: &mut Stage,
+    ) -> Result<(), WorkerError> {
+        match record {
+            Record::SQLCommand(commands) => {
+                let conn = self
+                    .pool
+                    .get()
+                    .await
+                    .expect("Failed to acquire a Postgres connection");
+
+                conn.execute("BEGIN", &[])
+                    .await
+                    .expect("Failed to begin transaction");
+
+                for command in commands {
+                    conn.execute(command, &[])
+                        .await
+                        .expect("Failed to execute transaction");
+                }
+
+                conn.execute("COMMIT", &[])
+                    .await
+                    .expect("Failed to commit transaction");
+            }
+            Record::None => (),
+            _ => {
+                panic!("The postgres storage stage only supports SQLCommand records");
+            }
+        }
+
+        stage.ops_count.inc(1);
+
+        Ok(())
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 impl gasket::framework::Worker<Stage> for Worker {
     async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
@@ -41,6 +83,11 @@ impl gasket::framework::Worker<Stage> for Worker {
             ChainEvent::Apply(point, record) => (point, record, true),
             ChainEvent::Undo(point, record) => (point, record, false),
             ChainEvent::Reset(_) => return Ok(()),
+            ChainEvent::MempoolAdd(_, record)
+            | ChainEvent::MempoolRemove(_, record)
+            | ChainEvent::MempoolConfirm(_, record) => {
+                return self.execute_mempool(record, stage).await;
+            }
         };
 
         match record {
@@ -11,10 +11,141 @@ use tracing::{error, info};
 
 use crate::framework::*;
 
+/// `LastWriteWins` keeps the versions of a value in a sorted set scored by
+/// slot. When the value written was already a version at an earlier slot,
+/// that slot is kept aside in `<key>.shadowed` so that an undo can put it back.
+const LWW_WRITE: &str = r#"
+local prev = redis.call('ZSCORE', KEYS[1], ARGV[2])
+if prev and tonumber(prev) < tonumber(ARGV[1]) then
+  redis.call('HSET', KEYS[2], ARGV[1] .. ':' .. ARGV[2], prev)
+end
+redis.call('ZADD', KEYS[1], ARGV[1], ARGV[2])
+"#;
+
+/// Takes out the version written at the given slot, bringing back the
+/// earlier slot of the same value if the write replaced one.
+const LWW_UNDO: &str = r#"
+local score = redis.call('ZSCORE', KEYS[1], ARGV[2])
+if score and tonumber(score) == tonumber(ARGV[1]) then
+  local field = ARGV[1] .. ':' .. ARGV[2]
+  local prev = redis.call('HGET', KEYS[2], field)
+  if prev then
+    redis.call('ZADD', KEYS[1], prev, ARGV[2])
+    redis.call('HDEL', KEYS[2], field)
+  else
+    redis.call('ZREM', KEYS[1], ARGV[2])
+  end
+end
+"#;
+
+/// Runs one of the `LastWriteWins` scripts. Scripts are sent whole rather
+/// than by hash, since within a transaction a missing script would only fail
+/// on `EXEC`.
+fn eval_lww(
+    conn: &mut redis::Connection,
+    script: &str,
+    key: String,
+    value: model::Value,
+    slot: u64,
+) -> Result<(), WorkerError> {
+    let shadowed = format!("{}.shadowed", key);
+
+    redis::cmd("EVAL")
+        .arg(script)
+        .arg(2)
+        .arg(key)
+        .arg(shadowed)
+        .arg(slot)
+        .arg(value)
+        .query::<()>(conn)
+        .or_restart()
+}
+
+fn apply_commands(
+    conn: &mut redis::Connection,
+    commands: Vec<model::CRDTCommand>,
+) -> Result<(), WorkerError> {
+    for command in commands {
+        match command {
+            model::CRDTCommand::GrowOnlySetAdd(key, value) => {
+                conn.sadd(key, value).or_restart()?;
+            }
+            model::CRDTCommand::TwoPhaseSetAdd(key, value) => {
+                conn.sadd(key, value).or_restart()?;
+            }
+            model::CRDTCommand::TwoPhaseSetRemove(key, value) => {
+                conn.sadd(format!("{}.ts", key), value).or_restart()?;
+            }
+            model::CRDTCommand::SetAdd(key, value) => {
+                conn.sadd(key, value).or_restart()?;
+            }
+            model::CRDTCommand::SetRemove(key, value) => {
+                conn.srem(key, value).or_restart()?;
+            }
+            model::CRDTCommand::LastWriteWins(key, value, slot) => {
+                eval_lww(conn, LWW_WRITE, key, value, slot)?;
+            }
+            model::CRDTCommand::LastWriteWinsUndo(key, value, slot) => {
+                eval_lww(conn, LWW_UNDO, key, value, slot)?;
+            }
+            model::CRDTCommand::SortedSetAdd(key, value, delta) => {
+                conn.zincr(key, value, delta).or_restart()?;
+            }
+            model::CRDTCommand::SortedSetRemove(key, value, delta) => {
+                conn.zincr(&key, value, delta).or_restart()?;
+
+                // removal of dangling scores  (aka garage collection)
+                conn.zrembyscore(&key, 0, 0).or_restart()?;
+            }
+            model::CRDTCommand::AnyWriteWins(key, value) => {
+                conn.set(key, value).or_restart()?;
+            }
+            model::CRDTCommand::PNCounter(key, value) => {
+                conn.incr(key, value).or_restart()?;
+            }
+            model::CRDTCommand::HashSetValue(key, member, value) => {
+                conn.hset(key, member, value).or_restart()?;
+            }
+            model::CRDTCommand::HashCounter(key, member, delta) => {
+                conn.hincr(key, member, delta).or_restart()?;
+            }
+            model::CRDTCommand::HashUnsetKey(key, member) => {
+                conn.hdel(member, key).or_restart()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub struct Worker {
     pool: Pool<RedisConnectionManager>,
 }
 
+impl Worker {
+    // mempool commands are applied atomically as well, but they don't move
+    // the cursor since they don't belong to any block
+    fn execute_mempool(&mut self, record: Record, stage: &mut Stage) -> Result<(), WorkerError> {
+        match record {
+            Record::CRDTCommand(commands) => {
+                let mut conn = self.pool.get().or_restart()?;
+
+                redis::cmd("MULTI").query(conn.deref_mut()).or_retry()?;
+                apply_commands(conn.deref_mut(), commands)?;
+                redis::cmd("EXEC").query(conn.deref_mut()).or_retry()?;
+            }
+            Record::None => (),
+            _ => {
+                panic!("The redis storage stage only supports CRDTCommand records");
+            }
+        }
+
+        stage.ops_count.inc(1);
+
+        Ok(())
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 impl gasket::framework::Worker<Stage> for Worker {
     async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
@@ -42,6 +173,11 @@ impl gasket::framework::Worker<Stage> for Worker {
             ChainEvent::Apply(point, record) => (point, record.clone(), true),
             ChainEvent::Undo(point, record) => (point, record.clone(), false),
             ChainEvent::Reset(_) => return Ok(()),
+            ChainEvent::MempoolAdd(_, record)
+            | ChainEvent::MempoolRemove(_, record)
+            | ChainEvent::MempoolConfirm(_, record) => {
+                return self.execute_mempool(record.clone(), stage);
+            }
         };
 
         match record {
@@ -50,52 +186,7 @@ impl gasket::framework::Worker<Stage> for Worker {
 
                 redis::cmd("MULTI").query(conn.deref_mut()).or_retry()?;
 
-                for command in commands {
-                    match command {
-                        model::CRDTCommand::GrowOnlySetAdd(key, value) => {
-                            conn.sadd(key, value).or_restart()?;
-                        }
-                        model::CRDTCommand::TwoPhaseSetAdd(key, value) => {
-                            conn.sadd(key, value).or_restart()?;
-                        }
-                        model::CRDTCommand::TwoPhaseSetRemove(key, value) => {
-                            conn.sadd(format!("{}.ts", key), value).or_restart()?;
-                        }
-                        model::CRDTCommand::SetAdd(key, value) => {
-                            conn.sadd(key, value).or_restart()?;
-                        }
-                        model::CRDTCommand::SetRemove(key, value) => {
-                            conn.srem(key, value).or_restart()?;
-                        }
-                        model::CRDTCommand::LastWriteWins(key, value, slot) => {
-                            conn.zadd(key, value, slot).or_restart()?;
-                        }
-                        model::CRDTCommand::SortedSetAdd(key, value, delta) => {
-                            conn.zincr(key, value, delta).or_restart()?;
-                        }
-                        model::CRDTCommand::SortedSetRemove(key, value, delta) => {
-                            conn.zincr(&key, value, delta).or_restart()?;
-
-                            // removal of dangling scores  (aka garage collection)
-                            conn.zrembyscore(&key, 0, 0).or_restart()?;
-                        }
-                        model::CRDTCommand::AnyWriteWins(key, value) => {
-                            conn.set(key, value).or_restart()?;
-                        }
-                        model::CRDTCommand::PNCounter(key, value) => {
-                            conn.incr(key, value).or_restart()?;
-                        }
-                        model::CRDTCommand::HashSetValue(key, member, value) => {
-                            conn.hset(key, member, value).or_restart()?;
-                        }
-                        model::CRDTCommand::HashCounter(key, member, delta) => {
-                            conn.hincr(key, member, delta).or_restart()?;
-                        }
-                        model::CRDTCommand::HashUnsetKey(key, member) => {
-                            conn.hdel(member, key).or_restart()?;
-                        }
-                    }
-                }
+                apply_commands(conn.deref_mut(), commands)?;
 
                 if is_apply {
                     if !stage.cursor.is_empty()
@@ -224,7 +315,7 @@ impl ToRedisArgs for model::Value {
             model::Value::String(x) => x.write_redis_args(out),
             model::Value::BigInt(x) => x.to_string().write_redis_args(out),
             model::Value::Cbor(x) => x.write_redis_args(out),
-            model::Value::Json(x) => todo!("{}", x),
+            model::Value::Json(x) => model::json_text(x).write_redis_args(out),
         }
     }
 }